@@ -0,0 +1,92 @@
+//! Named board sections.
+
+use crate::{overrides::Env, Layers, Origin};
+use anyhow::{anyhow, bail, Result};
+use toml::Value;
+
+/// The key of the table holding named board sections.
+pub const BOARD_KEY: &str = "board";
+
+/// The environment variable selecting a board section.
+///
+/// The `--board` option of the `drone` utility takes precedence over it. It
+/// should be set for `cargo build` to make `drone-ld` use the same board.
+pub const BOARD_ENV: &str = "DRONE_BOARD";
+
+/// Top-level tables which can be overridden by a board section.
+pub const BOARD_TABLES: &[&str] = &["memory", "probe", "log"];
+
+impl Layers {
+    /// Removes all `[board.<name>]` sections and merges the one selected by
+    /// `board`, or by the `DRONE_BOARD` variable in `env` if `board` is
+    /// `None`, on top of the configuration.
+    pub(crate) fn select_board(&mut self, board: Option<&str>, env: Env<'_>) -> Result<()> {
+        let boards = self.value.as_table_mut().unwrap().remove(BOARD_KEY);
+        let board_origins = self
+            .origins
+            .iter()
+            .filter(|(leaf, _)| leaf.starts_with(&format!("{}.", BOARD_KEY)))
+            .map(|(leaf, origin)| (leaf.clone(), origin.clone()))
+            .collect::<Vec<_>>();
+        for (leaf, _) in &board_origins {
+            self.origins.remove(leaf);
+        }
+        self.env_vars.insert(BOARD_ENV.to_string());
+        let name = match board {
+            Some(board) => board.to_string(),
+            None => env(BOARD_ENV).unwrap_or_default(),
+        };
+        if name.is_empty() {
+            return Ok(());
+        }
+        let mut boards = match boards {
+            Some(Value::Table(boards)) => boards,
+            Some(_) => bail!("`{}` must be a table", BOARD_KEY),
+            None => bail!("Unknown board `{}`, there are no `[{}.*]` sections", name, BOARD_KEY),
+        };
+        let available = boards.keys().cloned().collect::<Vec<_>>().join(", ");
+        let board = boards
+            .remove(&name)
+            .ok_or_else(|| anyhow!("Unknown board `{}`, expected one of: {}", name, available))?;
+        let prefix = format!("{}.{}.", BOARD_KEY, name);
+        let board = match board {
+            Value::Table(board) => board,
+            _ => bail!("`{}.{}` must be a table", BOARD_KEY, name),
+        };
+        for (table, value) in board {
+            if !BOARD_TABLES.contains(&table.as_str()) {
+                bail!(
+                    "`{}{}` can't be overridden by a board, expected one of: {}",
+                    prefix,
+                    table,
+                    BOARD_TABLES.join(", ")
+                );
+            }
+            let mut layer = toml::map::Map::new();
+            layer.insert(table, value);
+            self.merge_layer(Value::Table(layer), &Origin::Env(BOARD_ENV.to_string()));
+        }
+        for (leaf, origin) in board_origins {
+            if let Some(key) = leaf.strip_prefix(&prefix) {
+                self.origins.insert(key.to_string(), origin);
+                self.board_keys.insert(key.to_string());
+            }
+        }
+        self.board = Some(name);
+        Ok(())
+    }
+
+    /// Returns the key under which the value of `key` is defined in its
+    /// source file.
+    pub(crate) fn source_key(&self, key: &str) -> String {
+        let prefix = format!("{}.", key);
+        match &self.board {
+            Some(board)
+                if self.board_keys.iter().any(|leaf| leaf == key || leaf.starts_with(&prefix)) =>
+            {
+                format!("{}.{}.{}", BOARD_KEY, board, key)
+            }
+            _ => key.to_string(),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Build script helpers.
+//!
+//! Generates a Rust module with the memory layout constants, so the firmware
+//! code can refer to the memory blocks and heaps defined in `Drone.toml`:
+//!
+//! ```no_run
+//! // build.rs
+//! use std::{env, path::Path};
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     let out_dir = env::var_os("OUT_DIR").unwrap();
+//!     drone_config::build::generate_consts(Path::new(&out_dir))
+//! }
+//! ```
+//!
+//! ```ignore
+//! // src/lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/drone_layout.rs"));
+//!
+//! fn ram_end() -> usize {
+//!     memory::ram::ORIGIN + memory::ram::SIZE
+//! }
+//! ```
+
+use crate::{Config, HeapBlock, Layers, MemoryBlock, CONFIG_NAME, LOCAL_CONFIG_NAME};
+use anyhow::{anyhow, bail, Result};
+use std::{env, fmt::Write as _, fs, path::Path};
+
+/// The name of the generated file.
+pub const LAYOUT_FILE_NAME: &str = "drone_layout.rs";
+
+/// Reads the configuration from the `CARGO_MANIFEST_DIR` environment
+/// variable path and writes [`LAYOUT_FILE_NAME`] into `out_dir`.
+///
+/// The generated file contains the `memory` module with `ORIGIN` and `SIZE`
+/// constants for every memory block, and the `heap` module with `SIZE` and
+/// `POOLS` constants for every heap, plus `ORIGIN` for the extra heaps and
+/// `TRACE_PORT` for the heaps with `trace-port`.
+///
+/// Must be called from a build script, as it calls [`rerun_if_changed`].
+pub fn generate_consts(out_dir: &Path) -> Result<()> {
+    let layers = rerun_if_changed()?;
+    let config = Config::from_layers(&layers)?;
+    fs::write(out_dir.join(LAYOUT_FILE_NAME), render(&config)?)?;
+    Ok(())
+}
+
+/// Reads the configuration layers from the `CARGO_MANIFEST_DIR` environment
+/// variable path and tells Cargo to rerun the build script when any of them
+/// changes.
+///
+/// Must be called from a build script, as it prints `cargo:rerun-if-changed`
+/// for `Drone.toml`, every file it extends, and `Drone.local.toml` even if it
+/// doesn't exist yet, and `cargo:rerun-if-env-changed` for every environment
+/// variable read while building the configuration.
+pub fn rerun_if_changed() -> Result<Layers> {
+    let crate_root = env::var_os("CARGO_MANIFEST_DIR")
+        .ok_or_else(|| anyhow!("`CARGO_MANIFEST_DIR` is not set"))?;
+    let crate_root = Path::new(&crate_root).canonicalize()?;
+    let layers = Layers::read(&crate_root, None)?;
+    for line in rerun_lines(&crate_root, &layers) {
+        println!("{}", line);
+    }
+    Ok(layers)
+}
+
+fn rerun_lines(crate_root: &Path, layers: &Layers) -> Vec<String> {
+    let mut files = layers.files.clone();
+    let local = crate_root.join(LOCAL_CONFIG_NAME);
+    if !files.contains(&local) {
+        files.push(local);
+    }
+    let files = files.iter().map(|file| format!("cargo:rerun-if-changed={}", file.display()));
+    let vars = layers.env_vars.iter().map(|var| format!("cargo:rerun-if-env-changed={}", var));
+    files.chain(vars).collect()
+}
+
+/// Renders the constants module for `config`.
+pub fn render(config: &Config) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "// Generated by drone-config from {}. Do not edit.", CONFIG_NAME)?;
+    writeln!(out)?;
+    writeln!(out, "/// Memory blocks.")?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "pub mod memory {{")?;
+    let mut blocks = vec![("flash", &config.memory.flash), ("ram", &config.memory.ram)];
+    let mut extra = config.memory.extra.iter().map(|(n, b)| (n.as_str(), b)).collect::<Vec<_>>();
+    extra.sort_by_key(|(name, _)| *name);
+    blocks.extend(extra);
+    for (name, block) in blocks {
+        memory_block(&mut out, name, block)?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "/// Heaps.")?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "pub mod heap {{")?;
+    heap_block(&mut out, "main", None, &config.heap.main)?;
+    let mut extra = config.heap.extra.iter().collect::<Vec<_>>();
+    extra.sort_by_key(|(name, _)| *name);
+    for (name, heap) in extra {
+        heap_block(&mut out, name, Some(heap.origin), &heap.block)?;
+    }
+    writeln!(out, "}}")?;
+    Ok(out)
+}
+
+fn memory_block(out: &mut String, name: &str, block: &MemoryBlock) -> Result<()> {
+    writeln!(out, "    /// `memory.{}` block.", name)?;
+    writeln!(out, "    pub mod {} {{", module_name(name)?)?;
+    writeln!(out, "        /// The first address.")?;
+    writeln!(out, "        pub const ORIGIN: usize = {:#010x};", block.origin)?;
+    writeln!(out, "        /// The size in bytes.")?;
+    writeln!(out, "        pub const SIZE: usize = {:#x};", block.size)?;
+    writeln!(out, "    }}")?;
+    Ok(())
+}
+
+fn heap_block(out: &mut String, name: &str, origin: Option<u64>, block: &HeapBlock) -> Result<()> {
+    writeln!(out, "    /// `heap.{}` heap.", name)?;
+    writeln!(out, "    pub mod {} {{", module_name(name)?)?;
+    if let Some(origin) = origin {
+        writeln!(out, "        /// The first address.")?;
+        writeln!(out, "        pub const ORIGIN: usize = {:#010x};", origin)?;
+    }
+    writeln!(out, "        /// The size in bytes.")?;
+    writeln!(out, "        pub const SIZE: usize = {:#x};", block.size)?;
+    writeln!(out, "        /// Pools as `(block size, capacity)` pairs.")?;
+    write!(out, "        pub const POOLS: [(usize, usize); {}] = [", block.pools.len())?;
+    for (i, pool) in block.pools.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(out, "({}, {})", pool.block, pool.capacity)?;
+    }
+    writeln!(out, "];")?;
+    if let Some(trace_port) = block.trace_port {
+        writeln!(out, "        /// Log port of the heap trace.")?;
+        writeln!(out, "        pub const TRACE_PORT: u8 = {};", trace_port)?;
+    }
+    writeln!(out, "    }}")?;
+    Ok(())
+}
+
+/// Rust keywords, which need to be escaped to be used as module names.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+/// Keywords which can't be raw identifiers, so they get a `_` suffix instead.
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "super", "Self"];
+
+/// Converts a kebab-case configuration key into a module name.
+fn module_name(name: &str) -> Result<String> {
+    let module = name.replace('-', "_");
+    let valid = module.chars().next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && module.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && module != "_";
+    if !valid {
+        bail!("`{}` can't be used as a Rust module name", name);
+    }
+    if KEYWORDS.contains(&module.as_str()) {
+        Ok(format!("r#{}", module))
+    } else if PATH_KEYWORDS.contains(&module.as_str()) {
+        Ok(format!("{}_", module))
+    } else {
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn rerun_on_extended_files() {
+        let fixture = Fixture::new();
+        fixture.write("boards/common.toml", "[linker]\nplatform = \"arm\"\n");
+        fixture.write("boards/disco.toml", "extends = \"common.toml\"\n");
+        fixture.write(CONFIG_NAME, "extends = \"boards/disco.toml\"\n");
+        let layers = Layers::read(fixture.path(), None).unwrap();
+        let lines = rerun_lines(fixture.path(), &layers);
+        let files = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("cargo:rerun-if-changed="))
+            .collect::<Vec<_>>();
+        let expected = ["boards/common.toml", "boards/disco.toml", CONFIG_NAME, LOCAL_CONFIG_NAME]
+            .iter()
+            .map(|name| fixture.path().join(name).display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(files, expected);
+        assert!(lines.contains(&"cargo:rerun-if-env-changed=DRONE_BOARD".to_string()));
+    }
+
+    #[test]
+    fn module_names() {
+        for (name, module) in &[
+            ("ram", "ram"),
+            ("ccm-ram", "ccm_ram"),
+            ("_dma", "_dma"),
+            ("type", "r#type"),
+            ("static", "r#static"),
+            ("async", "r#async"),
+            ("self", "self_"),
+            ("super", "super_"),
+        ] {
+            assert_eq!(module_name(name).unwrap(), *module, "{}", name);
+        }
+        for name in &["", "_", "1ram", "ram.1", "ram block"] {
+            assert!(module_name(name).is_err(), "{}", name);
+        }
+    }
+}
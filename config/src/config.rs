@@ -7,6 +7,7 @@ use std::collections::HashMap;
 /// Config object.
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub memory: Memory,
@@ -18,6 +19,7 @@ pub struct Config {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Memory {
     pub flash: MemoryBlock,
@@ -28,15 +30,18 @@ pub struct Memory {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct MemoryBlock {
     #[serde(deserialize_with = "deserialize_size")]
-    pub size: u32,
-    pub origin: u32,
+    #[cfg_attr(feature = "schema", schemars(schema_with = "crate::schema::size"))]
+    pub size: u64,
+    pub origin: u64,
 }
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Heap {
     pub main: HeapBlock,
@@ -46,33 +51,41 @@ pub struct Heap {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct HeapBlock {
     #[serde(deserialize_with = "deserialize_size")]
-    pub size: u32,
+    #[cfg_attr(feature = "schema", schemars(schema_with = "crate::schema::size"))]
+    pub size: u64,
     pub pools: Vec<HeapPool>,
+    pub max_fragmentation: Option<f64>,
+    pub trace_port: Option<u32>,
 }
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct HeapExtra {
-    pub origin: u32,
+    pub origin: u64,
     #[serde(flatten)]
     pub block: HeapBlock,
 }
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct HeapPool {
     #[serde(deserialize_with = "deserialize_size")]
-    pub block: u32,
+    #[cfg_attr(feature = "schema", schemars(schema_with = "crate::schema::size"))]
+    pub block: u64,
     pub capacity: u32,
 }
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Linker {
     pub platform: String,
@@ -82,6 +95,7 @@ pub struct Linker {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Probe {
     pub gdb_client_command: String,
@@ -92,6 +106,7 @@ pub struct Probe {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct ProbeBmp {
     pub device: String,
@@ -100,6 +115,7 @@ pub struct ProbeBmp {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct ProbeJlink {
     pub gdb_server_command: String,
@@ -112,6 +128,7 @@ pub struct ProbeJlink {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct ProbeOpenocd {
     pub command: String,
@@ -121,6 +138,7 @@ pub struct ProbeOpenocd {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct Log {
     pub swo: Option<LogSwo>,
@@ -129,6 +147,7 @@ pub struct Log {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct LogSwo {
     pub reset_freq: u32,
@@ -138,6 +157,7 @@ pub struct LogSwo {
 
 #[non_exhaustive]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub struct LogDso {
     pub baud_rate: u32,
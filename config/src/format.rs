@@ -1,10 +1,17 @@
-use serde::de::{Deserialize, Deserializer, Error};
-use std::num::ParseIntError;
+use anyhow::{anyhow, Result};
+use serde::de::{Deserializer, Error, Unexpected, Visitor};
+use std::{convert::TryFrom, fmt};
 
 /// Parses an integer as in linker scripts.
-pub fn parse_size(src: &str) -> Result<u32, ParseIntError> {
+///
+/// Decimal, octal with a leading `0`, and hexadecimal with a leading `0x`
+/// numbers are accepted, optionally followed by `K`, `M`, or `G` suffix.
+pub fn parse_size(src: &str) -> Result<u64> {
     let mut range = 0..src.len();
-    let mult = if src.ends_with('M') {
+    let mult = if src.ends_with('G') {
+        range.end -= 1;
+        1024 * 1024 * 1024
+    } else if src.ends_with('M') {
         range.end -= 1;
         1024 * 1024
     } else if src.ends_with('K') {
@@ -16,18 +23,23 @@ pub fn parse_size(src: &str) -> Result<u32, ParseIntError> {
     let radix = if src.starts_with("0x") || src.starts_with("0X") {
         range.start += 2;
         16
-    } else if src.starts_with('0') && src.len() > 1 {
+    } else if src.starts_with('0') && range.len() > 1 {
         range.start += 1;
         8
     } else {
         10
     };
-    u32::from_str_radix(&src[range], radix).map(|x| x * mult)
+    u64::from_str_radix(&src[range], radix)
+        .map_err(|err| anyhow!("invalid size `{}`: {}", src, err))?
+        .checked_mul(mult)
+        .ok_or_else(|| anyhow!("size `{}` overflows 64 bits", src))
 }
 
 /// Returns a string representation of an integer as in linker scripts.
-pub fn format_size(value: u32) -> String {
-    if value > 0 && value % (1024 * 1024) == 0 {
+pub fn format_size(value: u64) -> String {
+    if value > 0 && value % (1024 * 1024 * 1024) == 0 {
+        format!("{}G", value / (1024 * 1024 * 1024))
+    } else if value > 0 && value % (1024 * 1024) == 0 {
         format!("{}M", value / (1024 * 1024))
     } else if value > 0 && value % 1024 == 0 {
         format!("{}K", value / 1024)
@@ -36,7 +48,79 @@ pub fn format_size(value: u32) -> String {
     }
 }
 
-/// Deserializes an integer as in linker scripts.
-pub fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
-    parse_size(&String::deserialize(deserializer)?).map_err(Error::custom)
+/// Deserializes an integer as in linker scripts, or a plain non-negative
+/// integer.
+pub fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    deserializer.deserialize_any(SizeVisitor)
+}
+
+struct SizeVisitor;
+
+impl<'de> Visitor<'de> for SizeVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a size string or a non-negative integer")
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<u64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<u64, E> {
+        u64::try_from(value).map_err(|_| E::invalid_value(Unexpected::Signed(value), &self))
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<u64, E> {
+        parse_size(value).map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for (input, output) in &[
+            ("0", 0),
+            ("1234", 1234),
+            ("010", 8),
+            ("0x1F", 0x1f),
+            ("0X1f", 0x1f),
+            ("0x08000000", 0x0800_0000),
+            ("0K", 0),
+            ("64K", 64 * 1024),
+            ("0x10K", 16 * 1024),
+            ("1M", 1024 * 1024),
+            ("4G", 4 * 1024 * 1024 * 1024),
+            ("0x100000000", 0x1_0000_0000),
+            ("0xFFFFFFFFFFFFFFFF", u64::MAX),
+            ("17179869183G", 17_179_869_183 * 1024 * 1024 * 1024),
+        ] {
+            assert_eq!(parse_size(input).unwrap(), *output, "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for input in
+            &["", "K", "0x", "0xG", "1.5M", "-1", "08", "12k", "1KB", "0x10000000000000000"]
+        {
+            assert!(parse_size(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_overflow() {
+        let err = parse_size("17179869184G").unwrap_err();
+        assert_eq!(err.to_string(), "size `17179869184G` overflows 64 bits");
+    }
+
+    #[test]
+    fn format_roundtrip() {
+        for value in &[0, 1, 1000, 1024, 1536, 1024 * 1024, 3 * 1024 * 1024 * 1024, u64::MAX] {
+            assert_eq!(parse_size(&format_size(*value)).unwrap(), *value);
+        }
+    }
 }
@@ -0,0 +1,371 @@
+//! Layered configuration files.
+
+use crate::{
+    overrides::{process_env, Env},
+    Diagnostic, CONFIG_NAME,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use toml::{map::Map, Value};
+
+/// The name of the local configuration override file.
+///
+/// The file is supposed to be git-ignored. It holds per-developer settings,
+/// like serial endpoints, and is deep-merged on top of `Drone.toml`.
+pub const LOCAL_CONFIG_NAME: &str = "Drone.local.toml";
+
+/// The key to specify a base configuration file, relative to the file
+/// containing the key.
+pub const EXTENDS_KEY: &str = "extends";
+
+/// Origin of a configuration value.
+#[derive(Clone, Debug)]
+pub enum Origin {
+    /// The value is read from a file.
+    File(PathBuf),
+    /// The value is overridden by an environment variable.
+    Env(String),
+}
+
+/// Configuration tree merged from several files.
+#[derive(Clone, Debug)]
+pub struct Layers {
+    /// The merged configuration tree.
+    pub value: Value,
+    /// Origins of the leaf values keyed by their dotted paths.
+    pub origins: BTreeMap<String, Origin>,
+    /// All contributing files, from the lowest precedence to the highest.
+    pub files: Vec<PathBuf>,
+    /// The selected board section.
+    pub board: Option<String>,
+    /// Environment variables read while building the tree, whether set or
+    /// not.
+    pub env_vars: BTreeSet<String>,
+    pub(crate) board_keys: BTreeSet<String>,
+    sources: HashMap<PathBuf, String>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Env(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+impl Layers {
+    /// Reads `Drone.toml` at `crate_root` together with all the files it
+    /// extends and the local override file.
+    ///
+    /// `board` selects a `[board.<name>]` section. If it is `None`, the
+    /// section is selected by the `DRONE_BOARD` environment variable.
+    pub fn read(crate_root: &Path, board: Option<&str>) -> Result<Self> {
+        let crate_root = crate_root.canonicalize()?;
+        let path = crate_root.join(CONFIG_NAME);
+        if !path.exists() {
+            bail!("`{}` not exists in `{}", CONFIG_NAME, crate_root.display());
+        }
+        Self::parse(&crate_root, &fs::read_to_string(&path)?, board)
+    }
+
+    /// Parses `source` as the contents of `Drone.toml` at `crate_root`.
+    ///
+    /// The files it extends and the local override file are read from the
+    /// disk. Then the board section selected by `board` or `DRONE_BOARD` is
+    /// merged, `DRONE_*` environment variable overrides are applied, and
+    /// `${VAR}` references in paths, endpoints, and commands are
+    /// interpolated.
+    pub fn parse(crate_root: &Path, source: &str, board: Option<&str>) -> Result<Self> {
+        let local = crate_root.join(LOCAL_CONFIG_NAME);
+        let local = if local.exists() { Some(fs::read_to_string(&local)?) } else { None };
+        Self::parse_with_local(crate_root, source, local.as_deref(), board)
+    }
+
+    /// Same as [`Layers::parse`], but takes the contents of the local
+    /// override file from `local` instead of the disk.
+    pub fn parse_with_local(
+        crate_root: &Path,
+        source: &str,
+        local: Option<&str>,
+        board: Option<&str>,
+    ) -> Result<Self> {
+        Self::parse_with_env(crate_root, source, local, board, &process_env)
+    }
+
+    /// Same as [`Layers::parse_with_local`], but looks up the environment
+    /// variables in `env` instead of the process environment.
+    pub(crate) fn parse_with_env(
+        crate_root: &Path,
+        source: &str,
+        local: Option<&str>,
+        board: Option<&str>,
+        env: Env<'_>,
+    ) -> Result<Self> {
+        let crate_root = crate_root.canonicalize()?;
+        let mut layers = Self {
+            value: Value::Table(Map::new()),
+            origins: BTreeMap::new(),
+            files: Vec::new(),
+            board: None,
+            env_vars: BTreeSet::new(),
+            board_keys: BTreeSet::new(),
+            sources: HashMap::new(),
+        };
+        layers.load(crate_root.join(CONFIG_NAME), source.to_string(), &mut Vec::new())?;
+        if let Some(local) = local {
+            layers.load(crate_root.join(LOCAL_CONFIG_NAME), local.to_string(), &mut Vec::new())?;
+        }
+        layers.select_board(board, env)?;
+        layers.apply_env(env)?;
+        layers.interpolate(env);
+        Ok(layers)
+    }
+
+    /// Returns the merged value at the dotted `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        key.split('.').try_fold(&self.value, Value::get)
+    }
+
+    /// Returns the origin of the value at the dotted `key`.
+    ///
+    /// If `key` is a table, the origin of its first leaf is returned. If
+    /// there is no such key, the origin of its closest parent is returned.
+    pub fn origin(&self, key: &str) -> Option<&Origin> {
+        let prefix = format!("{}.", key);
+        self.origins
+            .get(key)
+            .or_else(|| {
+                self.origins.iter().find(|(leaf, _)| leaf.starts_with(&prefix)).map(|(_, o)| o)
+            })
+            .or_else(|| {
+                let mut parent = key;
+                while let Some(position) = parent.rfind('.') {
+                    parent = &parent[..position];
+                    if let Some(origin) = self.origins.get(parent) {
+                        return Some(origin);
+                    }
+                }
+                None
+            })
+    }
+
+    /// Points `diagnostic` at the file, the line, and the column where its
+    /// key is defined.
+    pub fn locate(&self, diagnostic: &mut Diagnostic) {
+        if let Some(Origin::File(path)) = self.origin(&diagnostic.key) {
+            let key = self.source_key(&diagnostic.key);
+            if let Some(source) = self.sources.get(path) {
+                diagnostic.locate(source, &key);
+            }
+            diagnostic.file = Some(path.clone());
+        }
+    }
+
+    pub(crate) fn merge_layer(&mut self, layer: Value, origin: &Origin) {
+        merge(&mut self.value, layer, &mut Vec::new(), origin, &mut self.origins);
+    }
+
+    fn load(&mut self, path: PathBuf, source: String, chain: &mut Vec<PathBuf>) -> Result<()> {
+        if chain.contains(&path) {
+            let cycle = chain.iter().chain(Some(&path)).map(|path| format!("`{}`", path.display()));
+            bail!("`{}` cycle: {}", EXTENDS_KEY, cycle.collect::<Vec<_>>().join(" -> "));
+        }
+        let mut value = toml::from_str::<Value>(&source)
+            .map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        if let Some(extends) = value.as_table_mut().and_then(|table| table.remove(EXTENDS_KEY)) {
+            let extends = extends
+                .as_str()
+                .ok_or_else(|| anyhow!("{}: `{}` must be a string", path.display(), EXTENDS_KEY))?;
+            let base = path.parent().unwrap().join(extends);
+            let base = base
+                .canonicalize()
+                .map_err(|err| anyhow!("{}: `{}`: {}", path.display(), base.display(), err))?;
+            let source = fs::read_to_string(&base)?;
+            chain.push(path.clone());
+            self.load(base, source, chain)?;
+            chain.pop();
+        }
+        self.merge_layer(value, &Origin::File(path.clone()));
+        self.files.push(path.clone());
+        self.sources.insert(path, source);
+        Ok(())
+    }
+}
+
+fn merge(
+    base: &mut Value,
+    layer: Value,
+    path: &mut Vec<String>,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    match (base, layer) {
+        (Value::Table(base), Value::Table(layer)) => {
+            for (key, value) in layer {
+                path.push(key.clone());
+                if let Some(base) = base.get_mut(&key) {
+                    merge(base, value, path, origin, origins);
+                } else {
+                    record(&value, path, origin, origins);
+                    base.insert(key, value);
+                }
+                path.pop();
+            }
+        }
+        (base, layer) => {
+            let key = path.join(".");
+            let prefix = format!("{}.", key);
+            origins.retain(|leaf, _| *leaf != key && !leaf.starts_with(&prefix));
+            record(&layer, path, origin, origins);
+            *base = layer;
+        }
+    }
+}
+
+fn record(
+    value: &Value,
+    path: &mut Vec<String>,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    if let Value::Table(table) = value {
+        for (key, value) in table {
+            path.push(key.clone());
+            record(value, path, origin, origins);
+            path.pop();
+        }
+    } else {
+        origins.insert(path.join("."), origin.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    const COMMON: &str = r#"
+[memory.flash]
+size = "1M"
+origin = 0x08000000
+
+[memory.ram]
+size = "64K"
+origin = 0x20000000
+
+[linker]
+platform = "arm"
+include = ["common.ld", "extra.ld"]
+
+[probe.bmp]
+device = "stm32f429"
+gdb-endpoint = "/dev/ttyACM0"
+"#;
+
+    const DRONE_TOML: &str = r#"
+extends = "boards/common.toml"
+
+[memory.ram]
+size = "128K"
+
+[linker]
+include = ["layout.ld"]
+"#;
+
+    const LOCAL: &str = r#"
+[probe.bmp]
+gdb-endpoint = "/dev/ttyBmpGdb"
+"#;
+
+    fn read(fixture: &Fixture) -> Result<Layers> {
+        let source = fs::read_to_string(fixture.path().join(CONFIG_NAME))?;
+        let local = fs::read_to_string(fixture.path().join(LOCAL_CONFIG_NAME)).ok();
+        Layers::parse_with_env(fixture.path(), &source, local.as_deref(), None, &|_| None)
+    }
+
+    fn layered() -> Fixture {
+        let fixture = Fixture::new();
+        fixture.write("boards/common.toml", COMMON);
+        fixture.write(CONFIG_NAME, DRONE_TOML);
+        fixture.write(LOCAL_CONFIG_NAME, LOCAL);
+        fixture
+    }
+
+    fn origin(layers: &Layers, key: &str) -> String {
+        layers.origin(key).unwrap().to_string()
+    }
+
+    #[test]
+    fn deep_merge() {
+        let fixture = layered();
+        let layers = read(&fixture).unwrap();
+        assert_eq!(layers.get("memory.ram.size").unwrap().as_str(), Some("128K"));
+        assert_eq!(layers.get("memory.ram.origin").unwrap().as_integer(), Some(0x2000_0000));
+        assert_eq!(layers.get("memory.flash.size").unwrap().as_str(), Some("1M"));
+        assert_eq!(layers.get("probe.bmp.device").unwrap().as_str(), Some("stm32f429"));
+        assert_eq!(layers.get("probe.bmp.gdb-endpoint").unwrap().as_str(), Some("/dev/ttyBmpGdb"));
+        assert!(layers.get(EXTENDS_KEY).is_none());
+        let files = ["boards/common.toml", CONFIG_NAME, LOCAL_CONFIG_NAME]
+            .iter()
+            .map(|name| fixture.path().join(name))
+            .collect::<Vec<_>>();
+        assert_eq!(layers.files, files);
+    }
+
+    #[test]
+    fn array_replacement() {
+        let fixture = layered();
+        let layers = read(&fixture).unwrap();
+        let include = layers.get("linker.include").unwrap().as_array().unwrap();
+        assert_eq!(include, &vec![Value::from("layout.ld")]);
+        assert_eq!(layers.get("linker.platform").unwrap().as_str(), Some("arm"));
+        assert!(!layers.origins.contains_key("linker.include.0"));
+    }
+
+    #[test]
+    fn origins() {
+        let fixture = layered();
+        let layers = read(&fixture).unwrap();
+        let common = fixture.path().join("boards/common.toml").display().to_string();
+        let main = fixture.path().join(CONFIG_NAME).display().to_string();
+        let local = fixture.path().join(LOCAL_CONFIG_NAME).display().to_string();
+        for (key, file) in &[
+            ("memory.flash.size", &common),
+            ("memory.flash.origin", &common),
+            ("memory.ram.size", &main),
+            ("memory.ram.origin", &common),
+            ("linker.platform", &common),
+            ("linker.include", &main),
+            ("probe.bmp.device", &common),
+            ("probe.bmp.gdb-endpoint", &local),
+        ] {
+            assert_eq!(origin(&layers, key), **file, "{}", key);
+        }
+        assert_eq!(layers.origins.len(), 8);
+        assert_eq!(origin(&layers, "memory.ram"), common);
+        assert_eq!(origin(&layers, "linker.include.missing"), main);
+    }
+
+    #[test]
+    fn extends_cycle() {
+        let fixture = Fixture::new();
+        fixture.write(CONFIG_NAME, "extends = \"boards/a.toml\"\n");
+        fixture.write("boards/a.toml", "extends = \"b.toml\"\n");
+        fixture.write("boards/b.toml", "extends = \"../Drone.toml\"\n");
+        let chain = [CONFIG_NAME, "boards/a.toml", "boards/b.toml", CONFIG_NAME]
+            .iter()
+            .map(|name| format!("`{}`", fixture.path().join(name).display()))
+            .collect::<Vec<_>>();
+        let err = read(&fixture).unwrap_err().to_string();
+        assert_eq!(err, format!("`extends` cycle: {}", chain.join(" -> ")));
+        fixture.write(CONFIG_NAME, "extends = \"Drone.toml\"\n");
+        let path = fixture.path().join(CONFIG_NAME).display().to_string();
+        let err = read(&fixture).unwrap_err().to_string();
+        assert_eq!(err, format!("`extends` cycle: `{}` -> `{}`", path, path));
+    }
+}
@@ -4,13 +4,26 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::missing_errors_doc, clippy::module_name_repetitions, clippy::must_use_candidate)]
 
+mod board;
+pub mod build;
 mod config;
+#[cfg(test)]
+mod fixture;
 mod format;
+mod layers;
+mod overrides;
+#[cfg(feature = "schema")]
+mod schema;
+mod validate;
 
-pub use crate::{config::*, format::*};
+pub use crate::{board::*, config::*, format::*, layers::*, overrides::*, validate::*};
 
-use anyhow::{anyhow, bail, Result};
-use std::{env, fs::File, io::prelude::*, path::Path};
+#[cfg(feature = "schema")]
+pub use crate::schema::*;
+
+use crate::validate::check;
+use anyhow::{anyhow, Result};
+use std::{env, path::Path};
 
 /// The name of the Drone configuration file.
 pub const CONFIG_NAME: &str = "Drone.toml";
@@ -41,42 +54,42 @@ impl Config {
 
     /// Reads the configuration file at `crate_root` and returns a parsed
     /// object.
+    ///
+    /// The files it extends and the local override file are merged in. See
+    /// [`Layers`] for details.
     pub fn read(crate_root: &Path) -> Result<Self> {
-        let crate_root = crate_root.canonicalize()?;
-        let path = crate_root.join(CONFIG_NAME);
-        if !path.exists() {
-            bail!("`{}` not exists in `{}", CONFIG_NAME, crate_root.display());
-        }
-        let mut buffer = String::new();
-        let mut file = File::open(&path)?;
-        file.read_to_string(&mut buffer)?;
-        Self::parse(&buffer)
+        Self::read_for_board(crate_root, None)
     }
 
-    /// Parses config from the `string`.
-    pub fn parse(string: &str) -> Result<Self> {
-        let config = toml::from_str::<Self>(&string)?;
-        config.check_heaps()?;
-        Ok(config)
+    /// Same as [`Config::read`], but selects the `board` section instead of
+    /// the one named by the `DRONE_BOARD` environment variable.
+    pub fn read_for_board(crate_root: &Path, board: Option<&str>) -> Result<Self> {
+        Self::from_layers(&Layers::read(crate_root, board)?)
     }
 
-    fn check_heaps(&self) -> Result<()> {
-        let Self { heap: Heap { main, extra }, .. } = self;
-        main.check_pools()?;
-        for heap in extra.values() {
-            heap.block.check_pools()?;
+    /// Parses config from the merged `layers`.
+    pub fn from_layers(layers: &Layers) -> Result<Self> {
+        let config = layers.value.clone().try_into::<Self>()?;
+        let mut diagnostics = config.validate();
+        for diagnostic in &mut diagnostics {
+            layers.locate(diagnostic);
         }
-        Ok(())
+        check(&diagnostics)?;
+        Ok(config)
     }
-}
 
-impl HeapBlock {
-    fn check_pools(&self) -> Result<()> {
-        let Self { size, pools } = self;
-        let used: u32 = pools.iter().map(|pool| pool.block * pool.capacity).sum();
-        if used != *size {
-            bail!("{}: `heap.pools` adds up to {}, but `heap.size = {}", CONFIG_NAME, used, size);
+    /// Parses config from the `string`.
+    ///
+    /// The `extends` key and the local override file are not taken into
+    /// account.
+    pub fn parse(string: &str) -> Result<Self> {
+        let config = toml::from_str::<Self>(string)?;
+        let mut diagnostics = config.validate();
+        for diagnostic in &mut diagnostics {
+            let key = diagnostic.key.clone();
+            diagnostic.locate(string, &key);
         }
-        Ok(())
+        check(&diagnostics)?;
+        Ok(config)
     }
 }
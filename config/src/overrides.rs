@@ -0,0 +1,418 @@
+//! Environment variable overrides and interpolation.
+
+use crate::{Layers, Origin};
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashMap, env};
+use toml::{map::Map, Value};
+
+/// The prefix of environment variables overriding configuration values.
+///
+/// The rest of a variable name is one of [`ENV_KEYS`] in upper case, with `_`
+/// in place of `.` and `-`. For example, `DRONE_PROBE_OPENOCD_PORT` overrides
+/// `probe.openocd.port`, and `DRONE_LOG_SWO_SERIAL_ENDPOINT` overrides
+/// `log.swo.serial-endpoint`.
+pub const ENV_PREFIX: &str = "DRONE_";
+
+/// Configuration keys which can be overridden by `DRONE_*` environment
+/// variables. `*` stands for the name of an existing memory or heap block.
+/// The main heap is placed by the linker, so `heap.main.origin` is rejected.
+pub const ENV_KEYS: &[&str] = &[
+    "memory.*.size",
+    "memory.*.origin",
+    "heap.*.size",
+    "heap.*.origin",
+    "heap.*.max-fragmentation",
+    "heap.*.trace-port",
+    "linker.platform",
+    "linker.include",
+    "probe.gdb-client-command",
+    "probe.bmp.device",
+    "probe.bmp.gdb-endpoint",
+    "probe.jlink.gdb-server-command",
+    "probe.jlink.commander-command",
+    "probe.jlink.device",
+    "probe.jlink.interface",
+    "probe.jlink.speed",
+    "probe.jlink.port",
+    "probe.openocd.command",
+    "probe.openocd.port",
+    "probe.openocd.arguments",
+    "log.swo.reset-freq",
+    "log.swo.baud-rate",
+    "log.swo.serial-endpoint",
+    "log.dso.baud-rate",
+    "log.dso.serial-endpoint",
+];
+
+/// Configuration keys whose string values may reference environment variables
+/// as `${VAR}`: paths, endpoints, and commands. `$$` stands for a literal `$`,
+/// so `$${VAR}` is kept as `${VAR}`.
+pub const INTERPOLATED_KEYS: &[&str] = &[
+    "linker.include",
+    "probe.gdb-client-command",
+    "probe.bmp.device",
+    "probe.bmp.gdb-endpoint",
+    "probe.jlink.gdb-server-command",
+    "probe.jlink.commander-command",
+    "probe.openocd.command",
+    "probe.openocd.arguments",
+    "log.swo.serial-endpoint",
+    "log.dso.serial-endpoint",
+];
+
+/// Key of the main heap origin, which can't be overridden.
+const MAIN_HEAP_ORIGIN: &str = "heap.main.origin";
+
+/// Looks up an environment variable by name.
+pub(crate) type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Looks up a variable in the process environment.
+pub(crate) fn process_env(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+impl Layers {
+    /// Applies `DRONE_*` environment variable overrides from `env`.
+    ///
+    /// Only variables naming one of [`ENV_KEYS`] inside an existing top-level
+    /// table are taken into account. Missing tables below it are created.
+    pub(crate) fn apply_env(&mut self, env: Env<'_>) -> Result<()> {
+        let keys = self.env_keys();
+        self.env_vars.extend(keys.keys().cloned());
+        let main_origin = env_name(MAIN_HEAP_ORIGIN);
+        self.env_vars.insert(main_origin.clone());
+        if env(&main_origin).is_some() {
+            bail!(
+                "`{}`: `{}` can't be set, the main heap is placed by the linker",
+                main_origin,
+                MAIN_HEAP_ORIGIN
+            );
+        }
+        let mut vars = keys
+            .into_iter()
+            .filter_map(|(name, key)| Some((key, env(&name)?, name)))
+            .collect::<Vec<_>>();
+        vars.sort();
+        for (key, raw, name) in vars {
+            let value = override_value(self.get(&key), &raw)
+                .map_err(|err| anyhow!("`{}`: {}", name, err))?;
+            let path = key.split('.').collect::<Vec<_>>();
+            let (leaf, parents) = path.split_last().unwrap();
+            let mut table = self.value.as_table_mut().unwrap();
+            for parent in parents {
+                table = table
+                    .entry(*parent)
+                    .or_insert(Value::Table(Map::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| anyhow!("`{}`: `{}` is not a table", name, parent))?;
+            }
+            table.insert((*leaf).to_string(), value);
+            let prefix = format!("{}.", key);
+            self.origins.retain(|origin, _| *origin != key && !origin.starts_with(&prefix));
+            self.origins.insert(key, Origin::Env(name));
+        }
+        Ok(())
+    }
+
+    /// Replaces `${VAR}` references in the string values of
+    /// [`INTERPOLATED_KEYS`] with the values of the corresponding variables in
+    /// `env`. References to unset variables are left as is. The names of all
+    /// referenced variables are recorded in [`Layers::env_vars`].
+    pub(crate) fn interpolate(&mut self, env: Env<'_>) {
+        for key in INTERPOLATED_KEYS {
+            let mut path = key.split('.');
+            let first = path.next().unwrap();
+            let value =
+                path.try_fold(self.value.get_mut(first), |value, name| Some(value?.get_mut(name)));
+            let strings = match value.flatten() {
+                Some(Value::String(string)) => vec![string],
+                Some(Value::Array(array)) => array
+                    .iter_mut()
+                    .filter_map(|value| match value {
+                        Value::String(string) => Some(string),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => continue,
+            };
+            for string in strings {
+                let (value, vars) = interpolate(string, env);
+                *string = value;
+                self.env_vars.extend(vars);
+            }
+        }
+    }
+
+    /// Returns the keys which can be overridden by environment variables, by
+    /// the names of the variables.
+    fn env_keys(&self) -> HashMap<String, String> {
+        let mut keys = HashMap::new();
+        for pattern in ENV_KEYS {
+            let (top, rest) = pattern.split_once('.').unwrap();
+            let table = match self.value.get(top).and_then(Value::as_table) {
+                Some(table) => table,
+                None => continue,
+            };
+            if let Some(leaf) = rest.strip_prefix("*.") {
+                for (name, _) in table.iter().filter(|(_, value)| value.is_table()) {
+                    let key = format!("{}.{}.{}", top, name, leaf);
+                    if key != MAIN_HEAP_ORIGIN {
+                        keys.insert(env_name(&key), key);
+                    }
+                }
+            } else {
+                keys.insert(env_name(pattern), (*pattern).to_string());
+            }
+        }
+        keys
+    }
+}
+
+/// Returns the name of the environment variable overriding `key`.
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase().replace(&['.', '-'][..], "_"))
+}
+
+fn override_value(current: Option<&Value>, raw: &str) -> Result<Value> {
+    let parsed = || {
+        toml::from_str::<toml::map::Map<String, Value>>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+    };
+    match current {
+        None => Ok(parsed()
+            .filter(|value| !value.is_table())
+            .unwrap_or_else(|| Value::String(raw.to_string()))),
+        Some(Value::String(_)) => Ok(Value::String(raw.to_string())),
+        Some(Value::Table(_)) => bail!("can't override a table"),
+        Some(current) => match parsed() {
+            Some(value) if value.same_type(current) => Ok(value),
+            _ => bail!("expected a value of type {}", current.type_str()),
+        },
+    }
+}
+
+/// Replaces `${VAR}` references in `string` with the values from `env`, and
+/// `$$` with `$`. Returns the names of the referenced variables along with the
+/// result.
+fn interpolate(string: &str, env: Env<'_>) -> (String, Vec<String>) {
+    let mut output = String::with_capacity(string.len());
+    let mut vars = Vec::new();
+    let mut rest = string;
+    while let Some(position) = rest.find('$') {
+        output.push_str(&rest[..position]);
+        rest = &rest[position..];
+        if let Some(escaped) = rest.strip_prefix("$$") {
+            output.push('$');
+            rest = escaped;
+            continue;
+        }
+        if !rest.starts_with("${") {
+            output.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+        rest = &rest[2..];
+        let var = rest.find('}').and_then(|end| {
+            let name = &rest[..end];
+            let valid = !name.is_empty()
+                && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+            if !valid {
+                return None;
+            }
+            vars.push(name.to_string());
+            Some((env(name)?, end))
+        });
+        if let Some((value, end)) = var {
+            output.push_str(&value);
+            rest = &rest[end + 1..];
+        } else {
+            output.push_str("${");
+        }
+    }
+    output.push_str(rest);
+    (output, vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[memory.flash]
+size = "1M"
+origin = 0x08000000
+[memory.ram]
+size = "64K"
+origin = 0x20000000
+[heap.main]
+size = "16K"
+pools = []
+[heap.dma]
+size = "4K"
+origin = 0x20008000
+pools = []
+[linker]
+platform = "arm"
+include = ["${LAYOUT_DIR}/layout.ld"]
+[probe]
+gdb-client-command = "gdb"
+[probe.openocd]
+command = "${OPENOCD}"
+port = 3333
+arguments = ["-f", "$${OPENOCD}"]
+"#;
+
+    fn lookup<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| (*value).to_string())
+    }
+
+    fn parse(vars: &[(&str, &str)]) -> Result<Layers> {
+        Layers::parse_with_env(&env::temp_dir(), CONFIG, None, None, &lookup(vars))
+    }
+
+    #[test]
+    fn interpolate_string() {
+        let env = lookup(&[("VAR", "value")]);
+        for (input, output) in &[
+            ("${VAR}", "value"),
+            ("a${VAR}b${VAR}", "avaluebvalue"),
+            ("${UNSET}", "${UNSET}"),
+            ("$${VAR}", "${VAR}"),
+            ("$$${VAR}", "$value"),
+            ("$$$${VAR}", "$${VAR}"),
+            ("$$ $ $VAR $", "$ $ $VAR $"),
+            ("${VAR", "${VAR"),
+            ("${A-B}", "${A-B}"),
+            ("${}", "${}"),
+        ] {
+            assert_eq!(interpolate(input, &env).0, *output, "{}", input);
+        }
+        assert_eq!(interpolate("${A}${B_1}${A-B}${}$${C}", &env).1, vec!["A", "B_1"]);
+    }
+
+    #[test]
+    fn interpolate_keys() {
+        let layers = parse(&[("LAYOUT_DIR", "/layout")]).unwrap();
+        assert_eq!(layers.get("linker.include").unwrap()[0].as_str(), Some("/layout/layout.ld"));
+        assert_eq!(layers.get("probe.openocd.command").unwrap().as_str(), Some("${OPENOCD}"));
+        assert_eq!(layers.get("probe.openocd.arguments").unwrap()[1].as_str(), Some("${OPENOCD}"));
+    }
+
+    #[test]
+    fn overrides() {
+        let layers =
+            parse(&[("DRONE_PROBE_OPENOCD_PORT", "4444"), ("DRONE_HEAP_DMA_ORIGIN", "0x20009000")])
+                .unwrap();
+        assert_eq!(layers.get("probe.openocd.port").unwrap().as_integer(), Some(4444));
+        assert_eq!(layers.get("heap.dma.origin").unwrap().as_integer(), Some(0x2000_9000));
+        assert!(matches!(
+            layers.origin("probe.openocd.port"),
+            Some(Origin::Env(name)) if name == "DRONE_PROBE_OPENOCD_PORT"
+        ));
+        let err = parse(&[("DRONE_PROBE_OPENOCD_PORT", "\"4444\"")]).unwrap_err();
+        assert_eq!(err.to_string(), "`DRONE_PROBE_OPENOCD_PORT`: expected a value of type integer");
+    }
+
+    #[test]
+    fn main_heap_origin() {
+        let err = parse(&[("DRONE_HEAP_MAIN_ORIGIN", "0x20000000")]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`DRONE_HEAP_MAIN_ORIGIN`: `heap.main.origin` can't be set, the main heap is placed \
+             by the linker"
+        );
+        assert!(parse(&[]).unwrap().get("heap.main.origin").is_none());
+    }
+
+    #[test]
+    fn env_vars() {
+        let layers = parse(&[]).unwrap();
+        for name in &[
+            "DRONE_BOARD",
+            "DRONE_HEAP_DMA_ORIGIN",
+            "DRONE_HEAP_MAIN_ORIGIN",
+            "DRONE_PROBE_OPENOCD_PORT",
+            "LAYOUT_DIR",
+            "OPENOCD",
+        ] {
+            assert!(layers.env_vars.contains(*name), "{}", name);
+        }
+        let err = parse(&[("DRONE_BOARD", "disco")]).unwrap_err();
+        assert!(err.to_string().starts_with("Unknown board `disco`"), "{}", err);
+    }
+
+    #[test]
+    fn env_names() {
+        let keys = parse(&[]).unwrap().env_keys();
+        for (name, key) in &[
+            ("DRONE_MEMORY_FLASH_SIZE", "memory.flash.size"),
+            ("DRONE_HEAP_MAIN_SIZE", "heap.main.size"),
+            ("DRONE_HEAP_DMA_ORIGIN", "heap.dma.origin"),
+            ("DRONE_HEAP_DMA_TRACE_PORT", "heap.dma.trace-port"),
+            ("DRONE_PROBE_OPENOCD_PORT", "probe.openocd.port"),
+            ("DRONE_PROBE_BMP_GDB_ENDPOINT", "probe.bmp.gdb-endpoint"),
+        ] {
+            assert_eq!(keys.get(*name).map(String::as_str), Some(*key), "{}", name);
+        }
+        for name in &[
+            "DRONE_BOARD",
+            "DRONE_HEAP_MAIN_ORIGIN",
+            "DRONE_HEAP_DMA_POOLS",
+            "DRONE_HEAP_AUX_SIZE",
+            "DRONE_LOG_SWO_BAUD_RATE",
+        ] {
+            assert!(!keys.contains_key(*name), "{}", name);
+        }
+    }
+
+    #[cfg(feature = "schema")]
+    mod schema {
+        use super::*;
+        use crate::{schema, EXTENDS_KEY};
+        use schemars::schema::{RootSchema, Schema};
+        use std::collections::BTreeSet;
+
+        /// Collects the dotted keys of the non-table values, with `*` in place
+        /// of memory and heap block names.
+        fn leaves(root: &RootSchema, schema: &Schema, prefix: &str, keys: &mut BTreeSet<String>) {
+            let object = match schema {
+                Schema::Object(object) => object,
+                Schema::Bool(_) => return,
+            };
+            if let Some(reference) = &object.reference {
+                let definition = &root.definitions[reference.trim_start_matches("#/definitions/")];
+                return leaves(root, definition, prefix, keys);
+            }
+            let validation = match &object.object {
+                Some(validation) => validation,
+                None => {
+                    keys.insert(prefix.to_string());
+                    return;
+                }
+            };
+            let named = validation.properties.iter().map(|(name, schema)| (name.as_str(), schema));
+            let extra = validation.additional_properties.iter().map(|schema| ("*", &**schema));
+            for (name, schema) in named.chain(extra) {
+                let name = if prefix == "memory" || prefix == "heap" { "*" } else { name };
+                let key = if prefix.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}.{}", prefix, name)
+                };
+                leaves(root, schema, &key, keys);
+            }
+        }
+
+        #[test]
+        fn env_keys() {
+            let root = schema();
+            let mut keys = BTreeSet::new();
+            leaves(&root, &root.schema.clone().into(), "", &mut keys);
+            keys.remove(EXTENDS_KEY);
+            keys.remove("heap.*.pools");
+            assert_eq!(keys, ENV_KEYS.iter().map(ToString::to_string).collect());
+            assert!(INTERPOLATED_KEYS.iter().all(|key| ENV_KEYS.contains(key)));
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! JSON Schema of the configuration file.
+
+use crate::{Config, BOARD_KEY, BOARD_TABLES, CONFIG_NAME, EXTENDS_KEY};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{
+        InstanceType, Metadata, NumberValidation, ObjectValidation, RootSchema, Schema,
+        SchemaObject, StringValidation,
+    },
+};
+
+/// Regular expression matching the strings accepted by
+/// [`parse_size`](crate::parse_size).
+pub const SIZE_PATTERN: &str = r"^(0[xX][0-9a-fA-F]+|0[0-7]*|[1-9][0-9]*)[KMG]?$";
+
+/// Generates a JSON Schema of `Drone.toml`.
+///
+/// The schema is derived from the [`Config`] definition, so it is always in
+/// sync with the deserializer. It is suitable for TOML-aware editors.
+pub fn schema() -> RootSchema {
+    let mut settings = SchemaSettings::draft07();
+    settings.option_add_null_type = false;
+    let mut root = settings.into_generator().into_root_schema_for::<Config>();
+    root.schema.metadata().title = Some(CONFIG_NAME.to_string());
+    root.schema.object().properties.insert(
+        EXTENDS_KEY.to_string(),
+        string(None, "Path to a base configuration file, relative to this file"),
+    );
+    root.schema.object().properties.insert(BOARD_KEY.to_string(), board());
+    // Flattened maps of named blocks are not reflected by the derive.
+    for (table, extra) in &[("Memory", "MemoryBlock"), ("Heap", "HeapExtra")] {
+        if let Some(Schema::Object(object)) = root.definitions.get_mut(*table) {
+            object.object().additional_properties =
+                Some(Box::new(Schema::new_ref(format!("#/definitions/{}", extra))));
+        }
+    }
+    root
+}
+
+/// Schema of a size, either a string or a plain integer.
+pub(crate) fn size(_gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = string(
+        Some(SIZE_PATTERN),
+        "Size as in linker scripts: decimal, octal with a leading `0`, or hexadecimal with a \
+         leading `0x`, optionally followed by `K`, `M`, or `G` (e.g. \"256K\" or \"0x400\"), or a \
+         plain integer",
+    )
+    .into_object();
+    schema.instance_type = Some(vec![InstanceType::Integer, InstanceType::String].into());
+    schema.number =
+        Some(Box::new(NumberValidation { minimum: Some(0.0), ..NumberValidation::default() }));
+    schema.into()
+}
+
+/// Schema of the named board sections. The overridden tables are partial, so
+/// only their names are checked.
+fn board() -> Schema {
+    let tables = BOARD_TABLES
+        .iter()
+        .map(|&table| (table.to_owned(), object(ObjectValidation::default())))
+        .collect();
+    let board = object(ObjectValidation {
+        properties: tables,
+        additional_properties: Some(Box::new(Schema::Bool(false))),
+        ..ObjectValidation::default()
+    });
+    let mut schema = object(ObjectValidation {
+        additional_properties: Some(Box::new(board)),
+        ..ObjectValidation::default()
+    });
+    if let Schema::Object(object) = &mut schema {
+        object.metadata().description =
+            Some("Named board sections, selected with `--board` or `DRONE_BOARD`".to_string());
+    }
+    schema
+}
+
+fn object(validation: ObjectValidation) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        object: Some(Box::new(validation)),
+        ..SchemaObject::default()
+    }
+    .into()
+}
+
+fn string(pattern: Option<&str>, description: &str) -> Schema {
+    SchemaObject {
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_string()),
+            ..Metadata::default()
+        })),
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: pattern.map(ToString::to_string),
+            ..StringValidation::default()
+        })),
+        ..SchemaObject::default()
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HeapPool;
+    use regex::Regex;
+    use schemars::schema::SingleOrVec;
+
+    /// Checks `value` against the size schema the way a JSON Schema validator
+    /// does.
+    #[allow(clippy::cast_precision_loss)]
+    fn accepts(schema: &SchemaObject, value: &toml::Value) -> bool {
+        let types = match &schema.instance_type {
+            Some(SingleOrVec::Vec(types)) => types.clone(),
+            Some(SingleOrVec::Single(instance_type)) => vec![**instance_type],
+            None => Vec::new(),
+        };
+        match value {
+            toml::Value::Integer(value) => {
+                let minimum = schema.number.as_ref().and_then(|number| number.minimum);
+                types.contains(&InstanceType::Integer)
+                    && minimum.map_or(true, |minimum| *value as f64 >= minimum)
+            }
+            toml::Value::String(value) => {
+                let pattern = schema.string.as_ref().and_then(|string| string.pattern.as_ref());
+                types.contains(&InstanceType::String)
+                    && pattern.map_or(true, |pattern| Regex::new(pattern).unwrap().is_match(value))
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        let root = schema();
+        let definition = |name: &str| match &root.definitions[name] {
+            Schema::Object(object) => object.clone(),
+            Schema::Bool(_) => panic!("{}", name),
+        };
+        let size_schemas = [("MemoryBlock", "size"), ("HeapBlock", "size"), ("HeapPool", "block")]
+            .iter()
+            .map(|(name, property)| match &definition(name).object.unwrap().properties[*property] {
+                Schema::Object(object) => object.clone(),
+                Schema::Bool(_) => panic!("{}.{}", name, property),
+            })
+            .collect::<Vec<_>>();
+        for (value, valid) in &[
+            ("\"10K\"", true),
+            ("\"1G\"", true),
+            ("\"0x1000\"", true),
+            ("\"010\"", true),
+            ("4096", true),
+            ("0", true),
+            ("\"10KB\"", false),
+            ("\"-1\"", false),
+            ("-1", false),
+            ("\"\"", false),
+            ("1.5", false),
+        ] {
+            let source = format!("block = {}\ncapacity = 1\n", value);
+            let parsed = toml::from_str::<toml::Value>(&source).unwrap()["block"].clone();
+            for schema in &size_schemas {
+                assert_eq!(accepts(schema, &parsed), *valid, "{}", value);
+            }
+            assert_eq!(toml::from_str::<HeapPool>(&source).is_ok(), *valid, "{}", value);
+        }
+    }
+}
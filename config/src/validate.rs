@@ -0,0 +1,446 @@
+//! Semantic validation of the configuration.
+
+use crate::{format_size, Config, HeapBlock, MemoryBlock, CONFIG_NAME};
+use anyhow::{bail, Result};
+use std::{fmt, path::PathBuf};
+
+/// Supported values of the `linker.platform` option.
+pub const LINKER_PLATFORMS: &[&str] = &["arm", "riscv"];
+
+/// Required alignment of heap pool block sizes.
+pub const WORD_SIZE: u64 = 4;
+
+/// Number of log ports available for heap traces.
+pub const TRACE_PORTS: u32 = 32;
+
+/// A mistake found in the configuration.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// Dotted path of the offending key.
+    pub key: String,
+    /// Human-readable description of the mistake.
+    pub message: String,
+    /// Configuration file containing the offending key.
+    pub file: Option<PathBuf>,
+    /// Line number of the offending key in the configuration file.
+    pub line: Option<usize>,
+    /// Column number of the offending key in the configuration file.
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = self
+            .file
+            .as_ref()
+            .map_or_else(|| CONFIG_NAME.to_string(), |file| file.display().to_string());
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "{}:{}:{}: {}", file, line, column, self.message)
+            }
+            (Some(line), None) => write!(f, "{}:{}: {}", file, line, self.message),
+            (None, _) => write!(f, "{}: {}", file, self.message),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Points the diagnostic at the line and the column of `key` in the TOML
+    /// `source`.
+    pub(crate) fn locate(&mut self, source: &str, key: &str) {
+        let position = locate(source, key);
+        self.line = position.map(|(line, _)| line);
+        self.column = position.map(|(_, column)| column);
+    }
+}
+
+impl Config {
+    /// Checks the configuration for semantic mistakes.
+    ///
+    /// The returned diagnostics are not pointed at any file yet.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut push = |key: String, message: String| {
+            diagnostics.push(Diagnostic { key, message, file: None, line: None, column: None });
+        };
+        self.check_memory(&mut push);
+        self.check_heaps(&mut push);
+        self.check_linker(&mut push);
+        diagnostics
+    }
+
+    fn memory_blocks(&self) -> Vec<(String, &MemoryBlock)> {
+        let mut blocks = vec![
+            ("memory.flash".to_string(), &self.memory.flash),
+            ("memory.ram".to_string(), &self.memory.ram),
+        ];
+        let mut extra = self.memory.extra.iter().collect::<Vec<_>>();
+        extra.sort_by_key(|(name, _)| *name);
+        blocks.extend(extra.into_iter().map(|(name, block)| (format!("memory.{}", name), block)));
+        blocks
+    }
+
+    /// Returns the size of the address space of the target platform.
+    fn address_space(&self) -> u128 {
+        if self.linker.platform == "arm" { 1 << 32 } else { 1 << 64 }
+    }
+
+    fn check_memory(&self, push: &mut impl FnMut(String, String)) {
+        let blocks = self.memory_blocks();
+        for (key, block) in &blocks {
+            if end(block.origin, block.size) > self.address_space() {
+                push(
+                    format!("{}.size", key),
+                    format!("`{}` extends past the end of the address space", key),
+                );
+            }
+        }
+        for (i, (key_a, a)) in blocks.iter().enumerate() {
+            for (key_b, b) in &blocks[i + 1..] {
+                if overlaps((a.origin, a.size), (b.origin, b.size)) {
+                    push(format!("{}.origin", key_b), format!("`{}` overlaps `{}`", key_b, key_a));
+                }
+            }
+        }
+    }
+
+    fn check_heaps(&self, push: &mut impl FnMut(String, String)) {
+        let main = &self.heap.main;
+        check_pools("heap.main", main, push);
+        if main.size > self.memory.ram.size {
+            push(
+                "heap.main.size".to_string(),
+                format!(
+                    "`heap.main.size = {}` doesn't fit in `memory.ram.size = {}`",
+                    format_size(main.size),
+                    format_size(self.memory.ram.size)
+                ),
+            );
+        }
+        let blocks = self.memory_blocks();
+        let mut extra = self.heap.extra.iter().collect::<Vec<_>>();
+        extra.sort_by_key(|(name, _)| *name);
+        for (name, heap) in &extra {
+            let key = format!("heap.{}", name);
+            check_pools(&key, &heap.block, push);
+            let container = blocks.iter().find(|(_, block)| {
+                heap.origin >= block.origin
+                    && u128::from(heap.origin) < end(block.origin, block.size)
+            });
+            match container {
+                Some((block_key, block))
+                    if end(heap.origin, heap.block.size) > end(block.origin, block.size) =>
+                {
+                    push(
+                        format!("{}.size", key),
+                        format!("`{}` extends past the end of `{}`", key, block_key),
+                    );
+                }
+                Some(_) => {}
+                None => push(
+                    format!("{}.origin", key),
+                    format!(
+                        "`{}.origin = 0x{:08x}` is outside of every memory block",
+                        key, heap.origin
+                    ),
+                ),
+            }
+        }
+        let mut ports = main.trace_port.map(|port| ("main", port)).into_iter().collect::<Vec<_>>();
+        for (name, heap) in &extra {
+            if let Some(port) = heap.block.trace_port {
+                if let Some((used, _)) = ports.iter().find(|(_, used)| *used == port) {
+                    push(
+                        format!("heap.{}.trace-port", name),
+                        format!(
+                            "`heap.{}.trace-port = {}` is already used by `heap.{}`",
+                            name, port, used
+                        ),
+                    );
+                }
+                ports.push((name.as_str(), port));
+            }
+        }
+        for (i, (name_a, a)) in extra.iter().enumerate() {
+            for (name_b, b) in &extra[i + 1..] {
+                if overlaps((a.origin, a.block.size), (b.origin, b.block.size)) {
+                    push(
+                        format!("heap.{}.origin", name_b),
+                        format!("`heap.{}` overlaps `heap.{}`", name_b, name_a),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_linker(&self, push: &mut impl FnMut(String, String)) {
+        let platform = &self.linker.platform;
+        if !LINKER_PLATFORMS.contains(&platform.as_str()) {
+            push(
+                "linker.platform".to_string(),
+                format!(
+                    "unknown `linker.platform = \"{}\"`, expected one of: {}",
+                    platform,
+                    LINKER_PLATFORMS.join(", ")
+                ),
+            );
+        }
+    }
+}
+
+/// Fails with all `diagnostics` if there are any.
+pub(crate) fn check(diagnostics: &[Diagnostic]) -> Result<()> {
+    if !diagnostics.is_empty() {
+        let diagnostics = diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>();
+        bail!("{}", diagnostics.join("\n"));
+    }
+    Ok(())
+}
+
+fn check_pools(key: &str, heap: &HeapBlock, push: &mut impl FnMut(String, String)) {
+    let HeapBlock { size, pools, max_fragmentation, trace_port } = heap;
+    for (i, pool) in pools.iter().enumerate() {
+        if pool.block % WORD_SIZE != 0 {
+            push(
+                format!("{}.pools", key),
+                format!(
+                    "`{}.pools[{}].block = {}` is not a multiple of {}",
+                    key, i, pool.block, WORD_SIZE
+                ),
+            );
+        }
+    }
+    let used: u128 =
+        pools.iter().map(|pool| u128::from(pool.block) * u128::from(pool.capacity)).sum();
+    if used != u128::from(*size) {
+        push(
+            format!("{}.pools", key),
+            format!("`{}.pools` adds up to {}, but `{}.size = {}`", key, used, key, size),
+        );
+    }
+    if let Some(max_fragmentation) = max_fragmentation {
+        if !(0.0..=100.0).contains(max_fragmentation) {
+            push(
+                format!("{}.max-fragmentation", key),
+                format!(
+                    "`{}.max-fragmentation = {}` is not a percentage between 0 and 100",
+                    key, max_fragmentation
+                ),
+            );
+        }
+    }
+    if let Some(trace_port) = trace_port {
+        if *trace_port >= TRACE_PORTS {
+            push(
+                format!("{}.trace-port", key),
+                format!(
+                    "`{}.trace-port = {}` is not a log port between 0 and {}",
+                    key,
+                    trace_port,
+                    TRACE_PORTS - 1
+                ),
+            );
+        }
+    }
+}
+
+fn end(origin: u64, size: u64) -> u128 {
+    u128::from(origin) + u128::from(size)
+}
+
+fn overlaps((a_origin, a_size): (u64, u64), (b_origin, b_size): (u64, u64)) -> bool {
+    a_size > 0
+        && b_size > 0
+        && u128::from(a_origin) < end(b_origin, b_size)
+        && u128::from(b_origin) < end(a_origin, a_size)
+}
+
+/// Finds the line and the column numbers of the dotted `key` in the TOML
+/// `source`.
+///
+/// If the key itself is not present, the position of its closest present
+/// parent is returned.
+pub(crate) fn locate(source: &str, key: &str) -> Option<(usize, usize)> {
+    let target = key.split('.').collect::<Vec<_>>();
+    let mut table = Vec::new();
+    let mut best: Option<(usize, (usize, usize))> = None;
+    let mut consider = |path: &[String], position: (usize, usize)| {
+        let depth = path.iter().zip(&target).take_while(|(a, b)| a == *b).count();
+        let matched = depth == path.len().min(target.len());
+        if matched && depth > 0 && best.map_or(true, |(best, _)| depth > best) {
+            best = Some((depth, position));
+        }
+    };
+    for (i, line) in source.lines().enumerate() {
+        let position = (i + 1, line.len() - line.trim_start().len() + 1);
+        let line = line.trim();
+        if line.starts_with('[') {
+            let header = line.trim_start_matches('[');
+            let header = header.split(']').next().unwrap_or_default();
+            table = split_key(header);
+            consider(&table, position);
+        } else if let Some(eq) = line.find('=') {
+            let lhs = &line[..eq];
+            if lhs.is_empty() || lhs.starts_with(&['#', '{', '"'][..]) {
+                continue;
+            }
+            let mut path = table.clone();
+            path.extend(split_key(lhs));
+            consider(&path, position);
+        }
+    }
+    best.map(|(_, position)| position)
+}
+
+fn split_key(key: &str) -> Vec<String> {
+    key.split('.').map(|part| part.trim().trim_matches(&['"', '\''][..]).to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMORY: &str = r#"
+[memory.flash]
+size = "1M"
+origin = 0x08000000
+
+[memory.ram]
+size = "64K"
+origin = 0x20000000
+"#;
+
+    const HEAP: &str = r#"
+[heap.main]
+size = "16K"
+pools = [{ block = "4", capacity = 4096 }]
+"#;
+
+    const LINKER: &str = r#"
+[linker]
+platform = "arm"
+"#;
+
+    fn error(source: &str) -> String {
+        Config::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn valid() {
+        let config = Config::parse(&[MEMORY, HEAP, LINKER].concat()).unwrap();
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn memory_overlap() {
+        let memory = r#"
+[memory.ccm]
+size = "64K"
+  origin = 0x2000F000
+"#;
+        assert_eq!(
+            error(&[MEMORY, memory, HEAP, LINKER].concat()),
+            "Drone.toml:12:3: `memory.ccm` overlaps `memory.ram`"
+        );
+    }
+
+    #[test]
+    fn address_space_overflow() {
+        let memory = MEMORY.replace("origin = 0x08000000", "origin = 0xFFF80000");
+        assert_eq!(
+            error(&[&memory, HEAP, LINKER].concat()),
+            "Drone.toml:3:1: `memory.flash` extends past the end of the address space"
+        );
+        let linker = LINKER.replace("arm", "riscv");
+        assert!(Config::parse(&[&memory, HEAP, &linker].concat()).is_ok());
+    }
+
+    #[test]
+    fn main_heap_outside_ram() {
+        let heap = HEAP.replace("16K", "128K").replace("4096", "32768");
+        assert_eq!(
+            error(&[MEMORY, &heap, LINKER].concat()),
+            "Drone.toml:11:1: `heap.main.size = 128K` doesn't fit in `memory.ram.size = 64K`"
+        );
+    }
+
+    #[test]
+    fn extra_heap_outside_memory() {
+        let heap = r#"
+[heap.dma]
+origin = 0x30000000
+size = "1K"
+pools = [{ block = "4", capacity = 256 }]
+"#;
+        assert_eq!(
+            error(&[MEMORY, HEAP, heap, LINKER].concat()),
+            "Drone.toml:15:1: `heap.dma.origin = 0x30000000` is outside of every memory block"
+        );
+        let heap = heap.replace("0x30000000", "0x2000FE00");
+        assert_eq!(
+            error(&[MEMORY, HEAP, &heap, LINKER].concat()),
+            "Drone.toml:16:1: `heap.dma` extends past the end of `memory.ram`"
+        );
+    }
+
+    #[test]
+    fn extra_heap_overlap() {
+        let heap = r#"
+[heap.dma]
+origin = 0x20008000
+size = "1K"
+pools = [{ block = "4", capacity = 256 }]
+
+[heap.ext]
+origin = 0x20008200
+size = "1K"
+pools = [{ block = "4", capacity = 256 }]
+"#;
+        assert_eq!(
+            error(&[MEMORY, HEAP, heap, LINKER].concat()),
+            "Drone.toml:20:1: `heap.ext` overlaps `heap.dma`"
+        );
+    }
+
+    #[test]
+    fn misaligned_block() {
+        let heap = HEAP.replace(
+            r#"[{ block = "4", capacity = 4096 }]"#,
+            r#"[{ block = "6", capacity = 2 }, { block = "4", capacity = 4093 }]"#,
+        );
+        assert_eq!(
+            error(&[MEMORY, &heap, LINKER].concat()),
+            "Drone.toml:12:1: `heap.main.pools[0].block = 6` is not a multiple of 4"
+        );
+    }
+
+    #[test]
+    fn linker_platform() {
+        assert_eq!(
+            error(&[MEMORY, HEAP, &LINKER.replace("arm", "avr")].concat()),
+            "Drone.toml:15:1: unknown `linker.platform = \"avr\"`, expected one of: arm, riscv"
+        );
+        assert!(
+            error(&[MEMORY, HEAP, "\n[linker]\n"].concat()).contains("missing field `platform`")
+        );
+    }
+
+    #[test]
+    fn locate_keys() {
+        let source = r#"
+[memory]
+flash = { size = "1M", origin = 0x08000000 }
+
+[heap.main]
+  size = "16K"
+
+["heap".'dma']
+pools.block = 4
+"#;
+        assert_eq!(locate(source, "memory.flash.size"), Some((3, 1)));
+        assert_eq!(locate(source, "heap.main.size"), Some((6, 3)));
+        assert_eq!(locate(source, "heap.main.pools"), Some((5, 1)));
+        assert_eq!(locate(source, "heap.dma.pools"), Some((9, 1)));
+        assert_eq!(locate(source, "linker.platform"), None);
+    }
+}
@@ -1,12 +1,11 @@
 #![warn(clippy::pedantic)]
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use drone::{
     color::Color,
     templates::Registry,
     utils::{
-        crate_root, register_signals, run_command, run_wrapper,
-        search_rust_tool,
+        register_signals, resolve_target, run_command, run_wrapper, search_rust_tool, target_dir,
     },
 };
 use drone_config::Config;
@@ -15,7 +14,7 @@ use std::{
     env,
     ffi::{OsStr, OsString},
     fs::{create_dir_all},
-    path::Path,
+    path::{Path, PathBuf},
 };
 use tokio::process::Command;
 use drone::utils::WithSignals;
@@ -31,13 +30,17 @@ async fn main() {
 
 async fn run() -> Result<()> {
     let args = env::args_os().skip(1).collect::<Vec<_>>();
-    let config = Config::read_from_current_dir()?;
+    // The linker is invoked from the workspace root, so the crate root is
+    // taken from the environment set by Cargo.
+    let crate_root = env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("`CARGO_MANIFEST_DIR` is not set, `drone-ld` must be run by Cargo"))?;
+    let config = Config::read(&crate_root)?;
     let registry = Registry::new()?;
     let mut signals = register_signals()?;
 
-    let crate_root = crate_root().await?;
-    let target = drone::utils::resolve_target().await?;
-    let target = crate_root.join("target").join(target);
+    let target = resolve_target(&crate_root).await?;
+    let target = target_dir(&crate_root).await?.join(target);
     create_dir_all(&target)?;
     let stage_one = target.join("layout.ld.1");
     let stage_two = target.join("layout.ld.2");
@@ -110,7 +113,7 @@ async fn size_command(output: &OsStr) -> Result<Command> {
     Ok(command)
 }
 
-async fn run_size(mut command: Command) -> Result<HashMap<String, u32>> {
+async fn run_size(mut command: Command) -> Result<HashMap<String, u64>> {
     let stdout = String::from_utf8(command.output().await?.stdout)?;
     let mut map = HashMap::new();
     for line in stdout.lines() {
@@ -11,6 +11,8 @@ use structopt::StructOpt;
 use drone_config::parse_size;
 
 use crate::color::Color;
+use crate::heap::export::Format;
+use crate::log::InputSpec;
 use crate::probe::Log;
 use crate::probe::Probe;
 use crate::utils::de_from_str;
@@ -24,12 +26,31 @@ pub struct Cli {
     /// Coloring: auto, always, never
     #[structopt(long, default_value = "auto", parse(try_from_str = de_from_str))]
     pub color: Color,
+    #[structopt(flatten)]
+    pub package: PackageOpts,
     #[structopt(subcommand)]
     pub cmd: Cmd,
 }
 
+// Selection of the Drone project crate and its board. Not a doc comment, as
+// structopt would show it as the description of the whole program.
+#[derive(Debug, StructOpt)]
+pub struct PackageOpts {
+    /// Package of the Cargo workspace to operate on
+    #[structopt(short, long)]
+    pub package: Option<String>,
+    /// Path to Cargo.toml of the package or the workspace
+    #[structopt(long, parse(from_os_str))]
+    pub manifest_path: Option<PathBuf>,
+    /// Select a [board.<name>] section of Drone.toml (overrides DRONE_BOARD)
+    #[structopt(long)]
+    pub board: Option<String>,
+}
+
 #[derive(Debug, StructOpt)]
 pub enum Cmd {
+    /// Get or modify Drone.toml values
+    Config(ConfigCmd),
     /// Write the binary to ROM
     Flash(FlashCmd),
     /// Run a GDB session
@@ -38,6 +59,8 @@ pub enum Cmd {
     Heap(HeapCmd),
     /// Capture the log output
     Log(LogCmd),
+    /// Upgrade the current Drone project to this version of Drone
+    Migrate(MigrateCmd),
     /// Create a new Drone project
     New(NewCmd),
     /// Assert the reset signal
@@ -46,6 +69,45 @@ pub enum Cmd {
     Print(PrintCmd),
 }
 
+#[derive(Debug, StructOpt)]
+pub struct ConfigCmd {
+    /// Operate on Drone.local.toml instead of Drone.toml
+    #[structopt(long)]
+    pub local: bool,
+    #[structopt(subcommand)]
+    pub config_sub_cmd: ConfigSubCmd,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ConfigSubCmd {
+    /// Print the effective value of a dotted key (e.g. heap.main.size)
+    Get(ConfigGetCmd),
+    /// Set a dotted key to a value, preserving formatting and comments
+    Set(ConfigSetCmd),
+    /// Remove a dotted key
+    Unset(ConfigUnsetCmd),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ConfigGetCmd {
+    /// Dotted key
+    pub key: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ConfigSetCmd {
+    /// Dotted key
+    pub key: String,
+    /// New value, parsed as TOML unless the current value is a string
+    pub value: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ConfigUnsetCmd {
+    /// Dotted key
+    pub key: String,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct NewCmd {
     /// Where to create a new cargo package
@@ -58,10 +120,10 @@ pub struct NewCmd {
     /// Flash memory size in bytes (e.g. 1M for 1 megabyte, 512K for 512 kilobyte, or hexadecimal
     /// 0xffK)
     #[structopt(short, long, parse(try_from_str = parse_size))]
-    pub flash_size: u32,
+    pub flash_size: u64,
     /// RAM size in bytes (e.g. 256K for 256 kilobyte, or hexadecimal 0xffK)
     #[structopt(short, long, parse(try_from_str = parse_size))]
-    pub ram_size: u32,
+    pub ram_size: u64,
     /// Debug probe connected to the target device (run `drone print
     /// supported-devices` for the list of all available options)
     #[structopt(short, long, parse(try_from_str = de_from_str))]
@@ -80,7 +142,7 @@ pub struct NewCmd {
 
 #[derive(Debug, StructOpt)]
 pub struct HeapCmd {
-    /// Heap trace file obtained from the device
+    /// Heap trace file obtained from the device (`-` for stdin)
     #[structopt(
     short = "f",
     long,
@@ -94,7 +156,17 @@ pub struct HeapCmd {
     pub config: String,
     /// Maximum size of the heap
     #[structopt(short, long, parse(try_from_str = parse_size))]
-    pub size: Option<u32>,
+    pub size: Option<u64>,
+    /// Skip corrupted regions of the heap trace instead of failing
+    #[structopt(long)]
+    pub lenient: bool,
+    /// Output format of the trace summary and `dump`: text, json, csv
+    #[structopt(long, default_value = "text", parse(try_from_str = de_from_str))]
+    pub format: Format,
+    /// Raw log capture carrying the traces of every heap with `trace-port`,
+    /// or its `drone log --record` recording (`-` for stdin)
+    #[structopt(long, parse(from_os_str))]
+    pub capture: Option<PathBuf>,
     #[structopt(subcommand)]
     pub heap_sub_cmd: Option<HeapSubCmd>,
 }
@@ -103,13 +175,109 @@ pub struct HeapCmd {
 pub enum HeapSubCmd {
     /// Generate an optimized heap map from the given trace file
     Generate(HeapGenerateCmd),
+    /// Print every decoded packet of the given trace file
+    Dump(HeapDumpCmd),
+    /// Render an HTML report of the given trace file
+    Report(HeapReportCmd),
+    /// List blocks outstanding at the end of the given extended trace file,
+    /// and the block lifetimes
+    Leaks(HeapLeaksCmd),
+    /// Analyze the heap trace streamed from the device
+    Live(HeapLiveCmd),
+    /// Replay the given trace file against the configured heap pools
+    Simulate(HeapSimulateCmd),
+    /// Check trace files against the configured heap pools and print a JSON
+    /// report, failing on exhausted pools or excessive fragmentation
+    Check(HeapCheckCmd),
 }
 
 #[derive(Debug, StructOpt)]
 pub struct HeapGenerateCmd {
-    /// Number of pools
-    #[structopt(short, long, parse(try_from_str = parse_size))]
-    pub pools: u32,
+    /// Number of pools, or `auto` to pick it at the knee of the fragmentation
+    /// curve
+    #[structopt(short, long, parse(try_from_str = parse_heap_pools))]
+    pub pools: HeapPools,
+    /// Size pools from the simultaneous peak instead of the per-size maximum
+    /// loads
+    #[structopt(long)]
+    pub peak: bool,
+    /// Block size which must be present in the layout (can be repeated)
+    #[structopt(long = "pin", number_of_values = 1, parse(try_from_str = parse_size))]
+    pub pinned: Vec<u64>,
+    /// Minimum block size
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub min_block: Option<u64>,
+    /// Maximum block size
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub max_block: Option<u64>,
+    /// Minimum spare capacity of each pool in percents of its load
+    #[structopt(long, default_value = "0")]
+    pub spare: f64,
+    /// Write the layout into Drone.toml instead of printing it
+    #[structopt(long)]
+    pub write: bool,
+    /// Regenerate every heap, reading the trace of `[heap.<key>]` from
+    /// `<heaptrace>.<key>` (`<heaptrace>` itself for `main`)
+    #[structopt(long)]
+    pub all: bool,
+}
+
+/// Number of heap pools.
+#[derive(Debug, Clone, Copy)]
+pub enum HeapPools {
+    /// Pick the number automatically.
+    Auto,
+    /// Fixed number.
+    Count(u32),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapLiveCmd {
+    /// Reset before the operation
+    #[structopt(short, long)]
+    pub reset: bool,
+    /// Log port of the heap trace (`trace_port` option in `heap!` macro),
+    /// defaults to `heap.<config>.trace-port`, or 31
+    #[structopt(long)]
+    pub port: Option<u32>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapDumpCmd {}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapReportCmd {
+    /// Path to the output HTML file
+    #[structopt(long, parse(from_os_str))]
+    pub html: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapLeaksCmd {
+    /// Path to the compiled firmware file to symbolize the caller PCs
+    #[structopt(parse(from_os_str))]
+    pub firmware: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapSimulateCmd {}
+
+#[derive(Debug, StructOpt)]
+pub struct HeapCheckCmd {
+    /// Heap trace files to check (defaults to the heap trace file)
+    #[structopt(parse(from_os_str))]
+    pub trace_files: Vec<PathBuf>,
+    /// Maximum fragmentation in percents of the heap size (overrides
+    /// `heap.<config>.max-fragmentation`)
+    #[structopt(long)]
+    pub max_fragmentation: Option<f64>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MigrateCmd {
+    /// Apply all changes without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -143,6 +311,17 @@ pub struct LogCmd {
     /// Reset before the operation
     #[structopt(short, long)]
     pub reset: bool,
+    /// Read the log from the given source instead of the debug probe:
+    /// serial:<path>[@<baud-rate>], tcp:<host>:<port>, file:<path>, or `-` for
+    /// stdin, optionally prefixed with the swo: or dso: stream protocol
+    #[structopt(long, conflicts_with = "reset")]
+    pub input: Option<InputSpec>,
+    /// Save the raw log stream with host timestamps to the given file
+    #[structopt(long, parse(from_os_str))]
+    pub record: Option<PathBuf>,
+    /// Decode the raw log stream saved with `--record` instead of capturing
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["reset", "input", "record"])]
+    pub replay: Option<PathBuf>,
     /// Log output (format: \[path\]\[:port\]...)
     #[structopt(
     name = "OUTPUT",
@@ -170,10 +349,19 @@ pub struct PrintCmd {
 pub enum PrintSubCmd {
     /// Print the target triple of the current Drone project
     Target,
+    /// Print the merged configuration of the current Drone project along with
+    /// the origin of each value
+    Config,
+    /// Print a JSON Schema of Drone.toml for editor integration
+    ConfigSchema,
     /// Print a list of supported target devices, debug probes, and log types
     SupportedDevices,
 }
 
+fn parse_heap_pools(src: &str) -> Result<HeapPools, Error> {
+    if src == "auto" { Ok(HeapPools::Auto) } else { Ok(HeapPools::Count(src.parse()?)) }
+}
+
 fn parse_log_output(src: &str) -> Result<LogOutput, Error> {
     let mut chunks = src.split(':');
     let path = chunks.next().unwrap().to_owned();
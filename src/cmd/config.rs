@@ -0,0 +1,67 @@
+//! `drone config` command.
+
+use crate::{
+    cli::{ConfigCmd, ConfigGetCmd, ConfigSetCmd, ConfigSubCmd, ConfigUnsetCmd, PackageOpts},
+    color::Color,
+    edit::ConfigFile,
+    utils::{crate_root, inline_value},
+};
+use ansi_term::Color::Green;
+use anyhow::{anyhow, bail, Result};
+use drone_config::Layers;
+use std::path::Path;
+use toml::Value;
+
+/// Runs `drone config` command.
+pub async fn run(cmd: ConfigCmd, package: &PackageOpts, color: Color) -> Result<()> {
+    let ConfigCmd { local, config_sub_cmd } = cmd;
+    let crate_root = crate_root(package).await?;
+    let board = package.board.as_deref();
+    match config_sub_cmd {
+        ConfigSubCmd::Get(cmd) => get(cmd, &crate_root, board),
+        ConfigSubCmd::Set(cmd) => set(cmd, &crate_root, local, board, color),
+        ConfigSubCmd::Unset(cmd) => unset(cmd, &crate_root, local, board, color),
+    }
+}
+
+fn get(cmd: ConfigGetCmd, crate_root: &Path, board: Option<&str>) -> Result<()> {
+    let ConfigGetCmd { key } = cmd;
+    let layers = Layers::read(crate_root, board)?;
+    match layers.get(&key).ok_or_else(|| anyhow!("`{}` is not set", key))? {
+        Value::String(string) => println!("{}", string),
+        value => println!("{}", inline_value(value)),
+    }
+    Ok(())
+}
+
+fn set(
+    cmd: ConfigSetCmd,
+    crate_root: &Path,
+    local: bool,
+    board: Option<&str>,
+    color: Color,
+) -> Result<()> {
+    let ConfigSetCmd { key, value } = cmd;
+    let mut file = ConfigFile::open(crate_root, local, board)?;
+    file.set(&key, &value)?;
+    file.save()?;
+    eprintln!("     {} {}", color.bold_fg("Patched", Green), file.name());
+    Ok(())
+}
+
+fn unset(
+    cmd: ConfigUnsetCmd,
+    crate_root: &Path,
+    local: bool,
+    board: Option<&str>,
+    color: Color,
+) -> Result<()> {
+    let ConfigUnsetCmd { key } = cmd;
+    let mut file = ConfigFile::open(crate_root, local, board)?;
+    if !file.unset(&key)? {
+        bail!("`{}` is not set in `{}`", key, file.name());
+    }
+    file.save()?;
+    eprintln!("     {} {}", color.bold_fg("Patched", Green), file.name());
+    Ok(())
+}
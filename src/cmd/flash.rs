@@ -1,15 +1,19 @@
 //! `drone flash` command.
 
-use crate::{cli::FlashCmd, probe::Probe, templates::Registry, utils::register_signals};
+use crate::{
+    cli::{FlashCmd, PackageOpts},
+    probe::Probe,
+    templates::Registry,
+    utils::{read_config, register_signals},
+};
 use anyhow::Result;
-use drone_config as config;
 use std::convert::TryFrom;
 
 /// Runs `drone flash` command.
-pub async fn run(cmd: FlashCmd) -> Result<()> {
+pub async fn run(cmd: FlashCmd, package: &PackageOpts) -> Result<()> {
     let signals = register_signals()?;
     let registry = Registry::new()?;
-    let config = config::Config::read_from_current_dir()?;
+    let config = read_config(package).await?;
     let probe = Probe::try_from(&config)?;
     probe.flash(cmd, signals, registry, config).await
 }
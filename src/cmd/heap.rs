@@ -1,51 +1,146 @@
 //! `drone heap` command.
 
 use crate::{
-    cli::{HeapCmd, HeapGenerateCmd, HeapSubCmd},
+    cli::{
+        HeapCheckCmd, HeapCmd, HeapDumpCmd, HeapGenerateCmd, HeapLeaksCmd, HeapLiveCmd, HeapPools,
+        HeapReportCmd, HeapSimulateCmd, HeapSubCmd, PackageOpts,
+    },
     color::Color,
+    edit::ConfigFile,
     heap,
-    heap::TraceMap,
+    heap::{
+        export::{Format, PacketWriter, Summary},
+        layout::{Constraints, Occupancy, Optimized},
+        leaks::Tracker,
+        live::LiveTrace,
+        report::Report,
+        simulate::{Allocator, Failure, PoolLoad},
+        symbols::Symbols,
+        trace::{Corruption, Parser},
+        Load, TraceMap,
+    },
+    log::{OutputMap, OutputStream},
+    probe,
+    probe::{Log, Probe},
+    templates::Registry,
+    utils::{crate_root, read_config, register_signals, search_rust_tool, ser_to_string},
 };
-use ansi_term::Color::{Cyan, Yellow};
-use anyhow::{anyhow, Result};
-use drone_config::{self as config, format_size};
+use ansi_term::Color::{Cyan, Green, Red, Yellow};
+use anyhow::{anyhow, bail, Result};
+use drone_config::{self as config, format_size, HeapBlock};
 use prettytable::{cell, format, row, Table};
+use serde::Serialize;
 use std::{
-    fs::File,
-    io::{stderr, stdout},
+    convert::TryFrom,
+    fs,
+    io::{stderr, stdout, BufWriter, Cursor},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
 };
+use tokio::{process::Command, time};
+
+/// Refresh interval of `drone heap live` output.
+const LIVE_REFRESH: Duration = Duration::from_millis(500);
+
+/// Maximum number of corrupted trace regions to print.
+const MAX_CORRUPTIONS: usize = 10;
+
+/// Log port of the heap trace if not configured.
+const DEFAULT_TRACE_PORT: u32 = 31;
+
+/// `drone heap check` report.
+#[derive(Serialize)]
+struct CheckReport<'a> {
+    heap: &'a str,
+    size: u64,
+    max_fragmentation: Option<f64>,
+    passed: bool,
+    traces: Vec<TraceReport>,
+}
+
+/// `drone heap check` report for a single trace file.
+#[derive(Serialize)]
+struct TraceReport {
+    file: PathBuf,
+    passed: bool,
+    peak: u64,
+    peak_packet: usize,
+    fragmentation: u64,
+    fragmentation_percent: f64,
+    failed_allocations: usize,
+    failures: Vec<Failure>,
+    pools: Vec<PoolReport>,
+    skipped: u64,
+    corruptions: Vec<Corruption>,
+    skipped_packets: u64,
+}
+
+#[derive(Serialize)]
+struct PoolReport {
+    #[serde(flatten)]
+    load: PoolLoad,
+    headroom: f64,
+}
 
 /// Runs `drone heap` command.
-pub fn run(cmd: HeapCmd, color: Color) -> Result<()> {
-    let HeapCmd { trace_file, config: heap_config, size, heap_sub_cmd } = cmd;
-    let size = size.map_or_else(
-        || {
-            config::Config::read_from_current_dir().and_then(|config| {
-                if heap_config == "main" {
-                    Ok(config.heap.main.size)
-                } else {
-                    config
-                        .heap
-                        .extra
-                        .get(&heap_config)
-                        .map(|heap| heap.block.size)
-                        .ok_or_else(|| anyhow!("Unknown `{}` heap configuration", heap_config))
-                }
-            })
-        },
-        Ok,
-    )?;
+pub async fn run(cmd: HeapCmd, package: &PackageOpts, color: Color) -> Result<()> {
+    let HeapCmd { trace_file, config: heap_config, size, lenient, format, capture, heap_sub_cmd } =
+        cmd;
+    if let Some(capture) = capture {
+        return run_capture(&capture, heap_sub_cmd, package, lenient, color).await;
+    }
+    match heap_sub_cmd {
+        Some(HeapSubCmd::Dump(cmd)) => {
+            return dump(cmd, &trace_file, lenient, format, color);
+        }
+        Some(HeapSubCmd::Leaks(cmd)) => {
+            return leaks(cmd, &trace_file, lenient, color).await;
+        }
+        Some(HeapSubCmd::Report(cmd)) => {
+            return report(cmd, package, &trace_file, &heap_config, size, lenient, color).await;
+        }
+        Some(HeapSubCmd::Live(cmd)) => {
+            return live(cmd, package, &heap_config, size, color).await;
+        }
+        Some(HeapSubCmd::Simulate(cmd)) => {
+            return simulate(cmd, package, &trace_file, &heap_config, lenient, color).await;
+        }
+        Some(HeapSubCmd::Check(cmd)) => {
+            return check(cmd, package, trace_file, &heap_config, size, lenient).await;
+        }
+        Some(HeapSubCmd::Generate(cmd)) if cmd.all => {
+            return generate_all(cmd, package, &trace_file, lenient, color).await;
+        }
+        _ => {}
+    }
+    let size = if let Some(size) = size {
+        size
+    } else {
+        let config = read_config(package).await?;
+        heap_block(&config, &heap_config)?.size
+    };
     let mut trace = TraceMap::new();
-    if let Ok(file) = File::open(&trace_file) {
-        heap::read_trace(&mut trace, file, size)?;
+    if let Ok(reader) = heap::open_trace(&trace_file) {
+        let mut parser = Parser::new(reader, lenient)?;
+        let load = heap::read_trace(&mut trace, &mut parser, size, lenient)?;
+        print_corruptions(&trace_file, &parser, color);
+        print_skipped_packets(&trace_file, &load, color);
         if trace.is_empty() {
             eprintln!(
                 "{}: file `{}` is empty.",
                 color.bold_fg("warning", Yellow),
                 trace_file.display()
             );
+        } else if format == Format::Text || heap_sub_cmd.is_some() {
+            print_table(&trace, &load, size, color)?;
         } else {
-            print_table(&trace, size, color)?;
+            let summary = Summary::new(&trace, &load, size);
+            match format {
+                Format::Json => summary.write_json(&mut stdout())?,
+                Format::Csv => summary.write_csv(&mut stdout())?,
+                Format::Text => unreachable!(),
+            }
         }
     } else {
         eprintln!(
@@ -55,63 +150,860 @@ pub fn run(cmd: HeapCmd, color: Color) -> Result<()> {
         );
     }
     match heap_sub_cmd {
-        Some(HeapSubCmd::Generate(cmd)) => generate(cmd, &heap_config, &trace, size, color),
-        None => Ok(()),
+        Some(HeapSubCmd::Generate(cmd)) => {
+            generate(cmd, package, &heap_config, &trace, size, color).await
+        }
+        Some(
+            HeapSubCmd::Dump(_)
+            | HeapSubCmd::Report(_)
+            | HeapSubCmd::Leaks(_)
+            | HeapSubCmd::Live(_)
+            | HeapSubCmd::Simulate(_)
+            | HeapSubCmd::Check(_),
+        )
+        | None => Ok(()),
     }
 }
 
 /// Runs `drone heap generate` command.
-pub fn generate(
+pub async fn generate(
     cmd: HeapGenerateCmd,
+    package: &PackageOpts,
     config: &str,
     trace: &TraceMap,
-    size: u32,
+    size: u64,
     color: Color,
 ) -> Result<()> {
-    let HeapGenerateCmd { pools } = cmd;
-    if trace.is_empty() {
-        let layout = heap::layout::empty(size, pools);
-        heap::layout::render(&mut stdout(), config, &layout)?;
+    let mut file = if cmd.write {
+        Some(ConfigFile::open(&crate_root(package).await?, false, package.board.as_deref())?)
+    } else {
+        None
+    };
+    generate_heap(&cmd, file.as_mut(), config, trace, size, color)?;
+    if let Some(file) = file {
+        file.save()?;
+        eprintln!("     {} {}", color.bold_fg("Patched", Green), file.name());
+    }
+    Ok(())
+}
+
+/// Runs `drone heap generate --all` command.
+pub async fn generate_all(
+    cmd: HeapGenerateCmd,
+    package: &PackageOpts,
+    trace_file: &Path,
+    lenient: bool,
+    color: Color,
+) -> Result<()> {
+    let crate_root = crate_root(package).await?;
+    let config = config::Config::read_for_board(&crate_root, package.board.as_deref())?;
+    let mut file = if cmd.write {
+        Some(ConfigFile::open(&crate_root, false, package.board.as_deref())?)
+    } else {
+        None
+    };
+    for (key, block) in heaps(&config) {
+        let path = if key == "main" {
+            trace_file.to_path_buf()
+        } else {
+            let mut path = trace_file.as_os_str().to_owned();
+            path.push(format!(".{}", key));
+            PathBuf::from(path)
+        };
+        let mut trace = TraceMap::new();
+        if let Ok(reader) = heap::open_trace(&path) {
+            let mut parser = Parser::new(reader, lenient)?;
+            let load = heap::read_trace(&mut trace, &mut parser, block.size, lenient)?;
+            print_corruptions(&path, &parser, color);
+            print_skipped_packets(&path, &load, color);
+        }
+        if trace.is_empty() {
+            eprintln!(
+                "{}: no heap trace in `{}`, skipping [heap.{}]",
+                color.bold_fg("warning", Yellow),
+                path.display(),
+                key
+            );
+            continue;
+        }
+        eprintln!();
+        eprintln!("{}", color.bold_fg(&format!("{:=^80}", format!(" [heap.{}] ", key)), Cyan));
+        generate_heap(&cmd, file.as_mut(), key, &trace, block.size, color)?;
+    }
+    if let Some(file) = file {
+        file.save()?;
+        eprintln!("     {} {}", color.bold_fg("Patched", Green), file.name());
+    }
+    Ok(())
+}
+
+/// Runs `drone heap` command over a log capture of several heap traces.
+pub async fn run_capture(
+    capture: &Path,
+    heap_sub_cmd: Option<HeapSubCmd>,
+    package: &PackageOpts,
+    lenient: bool,
+    color: Color,
+) -> Result<()> {
+    let cmd = match heap_sub_cmd {
+        Some(HeapSubCmd::Generate(cmd)) => Some(cmd),
+        None => None,
+        Some(_) => bail!("`--capture` supports only the analysis and `generate`"),
+    };
+    let crate_root = crate_root(package).await?;
+    let config = config::Config::read_for_board(&crate_root, package.board.as_deref())?;
+    let log = Log::try_from(&config)?;
+    let heaps = heaps(&config)
+        .into_iter()
+        .filter_map(|(key, block)| block.trace_port.map(|port| (key, block, port)))
+        .collect::<Vec<_>>();
+    if heaps.is_empty() {
+        bail!("No heap has `trace-port` in Drone.toml");
+    }
+    let ports = heaps.iter().map(|(_, _, port)| *port).collect::<Vec<_>>();
+    let mut payloads = heap::demux::demux(heap::open_trace(capture)?, log, &ports)?;
+    let mut file = match &cmd {
+        Some(cmd) if cmd.write => {
+            Some(ConfigFile::open(&crate_root, false, package.board.as_deref())?)
+        }
+        _ => None,
+    };
+    let mut budget = Table::new();
+    budget.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    budget.set_titles(row![
+        color.bold("Heap"),
+        r->color.bold("Port"),
+        r->color.bold("Size"),
+        r->color.bold("Peak Load"),
+        r->color.bold("Sum of Max Loads"),
+        r->color.bold("Headroom"),
+    ]);
+    let (mut total_size, mut total_peak, mut total_used) = (0, 0, 0);
+    for (key, block, port) in heaps {
+        eprintln!();
+        eprintln!("{}", color.bold_fg(&format!("{:=^80}", format!(" [heap.{}] ", key)), Cyan));
+        let payload = payloads.remove(&port).unwrap_or_default();
+        let mut parser = Parser::new(Cursor::new(payload), lenient)?;
+        let mut trace = TraceMap::new();
+        let mut load = Load::default();
+        let mut allocator = Allocator::new(&block.pools);
+        for packet in &mut parser {
+            if let Some(packet) = heap::replay(&mut trace, &mut load, packet?, block.size, lenient)?
+            {
+                allocator.apply(&packet);
+            }
+        }
+        let source = PathBuf::from(format!("{}:{}", capture.display(), port));
+        print_corruptions(&source, &parser, color);
+        print_skipped_packets(&source, &load, color);
+        if trace.is_empty() {
+            eprintln!("{}: no heap trace on port {}", color.bold_fg("warning", Yellow), port);
+            continue;
+        }
+        print_table(&trace, &load, block.size, color)?;
+        eprintln!();
+        eprintln!("{}", color.bold_fg(&format!("{:=^80}", " SIMULATION "), Cyan));
+        print_pools(&allocator, color)?;
+        if let Some(cmd) = &cmd {
+            generate_heap(cmd, file.as_mut(), key, &trace, block.size, color)?;
+        }
+        let used =
+            trace.iter().map(|(size, entry)| u64::from(*size) * u64::from(entry.max)).sum::<u64>();
+        budget.add_row(row![
+            key,
+            r->port,
+            r->format_size(block.size),
+            r->load.peak,
+            r->used,
+            r->format!("{:.2}%", headroom(used, block.size)),
+        ]);
+        total_size += block.size;
+        total_peak += load.peak;
+        total_used += used;
+    }
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " RAM BUDGET "), Cyan));
+    budget.add_row(row![
+        color.bold("Total"),
+        "",
+        r->color.bold(&format_size(total_size)),
+        r->color.bold(&total_peak.to_string()),
+        r->color.bold(&total_used.to_string()),
+        r->color.bold(&format!("{:.2}%", headroom(total_used, total_size))),
+    ]);
+    budget.print(&mut stderr())?;
+    if let Some(file) = file {
+        file.save()?;
+        eprintln!("     {} {}", color.bold_fg("Patched", Green), file.name());
+    }
+    Ok(())
+}
+
+/// Generates the layout of `[heap.<config>]`, and either prints it or writes
+/// it into `file`.
+fn generate_heap(
+    cmd: &HeapGenerateCmd,
+    file: Option<&mut ConfigFile>,
+    config: &str,
+    trace: &TraceMap,
+    size: u64,
+    color: Color,
+) -> Result<()> {
+    let occupancy = if cmd.peak { Occupancy::Peak } else { Occupancy::Max };
+    let pools = match cmd.pools {
+        HeapPools::Auto => None,
+        HeapPools::Count(pools) => Some(pools),
+    };
+    let (layout, frag) = if trace.is_empty() {
+        let pools = pools.ok_or_else(|| anyhow!("`--pools auto` requires a heap trace"))?;
+        (heap::layout::empty(size, pools), None)
     } else {
-        let (layout, frag) = heap::layout::optimize(&trace, size, pools)?;
+        let constraints = Constraints {
+            pinned: cmd.pinned.clone(),
+            min_block: cmd.min_block,
+            max_block: cmd.max_block,
+            spare: cmd.spare,
+        };
+        let Optimized { layout, frag, curve } =
+            heap::layout::optimize(trace, size, pools, occupancy, &constraints)?;
+        if pools.is_none() {
+            eprintln!();
+            eprintln!("{}", color.bold_fg(&format!("{:=^80}", " FRAGMENTATION CURVE "), Cyan));
+            print_curve(&curve, layout.len() as u32, size, color)?;
+        }
         eprintln!();
         eprintln!("{}", color.bold_fg(&format!("{:=^80}", " OPTIMIZED LAYOUT "), Cyan));
+        (layout, Some(frag))
+    };
+    let write = file.is_some();
+    if let Some(file) = file {
+        let total = format_size(heap::layout::size(&layout));
+        file.set(&format!("heap.{}.size", config), &total)?;
+        file.set(&format!("heap.{}.pools", config), &heap::layout::render_pools(&layout))?;
+        heap::layout::render(&mut stderr(), config, &layout)?;
+    } else {
         heap::layout::render(&mut stdout(), config, &layout)?;
+    }
+    if let Some(frag) = frag {
         eprintln!(
             "# fragmentation: {}",
-            color.bold(&format!("{} / {:.2}%", frag, f64::from(frag) / f64::from(size) * 100.0))
+            color.bold(&format!("{} / {:.2}%", frag, frag as f64 / size as f64 * 100.0))
+        );
+        if !write {
+            eprintln!(
+                "# {}: replace the existing [heap.{}] section in Drone.toml, or pass `--write`",
+                color.bold_fg("hint", Cyan),
+                config
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs `drone heap dump` command.
+pub fn dump(
+    cmd: HeapDumpCmd,
+    trace_file: &Path,
+    lenient: bool,
+    format: Format,
+    color: Color,
+) -> Result<()> {
+    let HeapDumpCmd {} = cmd;
+    let mut parser = Parser::new(heap::open_trace(trace_file)?, lenient)?;
+    let stdout = stdout();
+    let mut writer = PacketWriter::new(BufWriter::new(stdout.lock()), format)?;
+    for packet in &mut parser {
+        writer.write(&packet?)?;
+    }
+    print_corruptions(trace_file, &parser, color);
+    Ok(())
+}
+
+/// Runs `drone heap leaks` command.
+pub async fn leaks(
+    cmd: HeapLeaksCmd,
+    trace_file: &Path,
+    lenient: bool,
+    color: Color,
+) -> Result<()> {
+    let HeapLeaksCmd { firmware } = cmd;
+    let symbols = match firmware {
+        Some(firmware) => read_symbols(&firmware).await?,
+        None => Symbols::default(),
+    };
+    let mut parser = Parser::new(heap::open_trace(trace_file)?, lenient)?;
+    let mut tracker = Tracker::default();
+    for packet in &mut parser {
+        tracker.apply(&packet?);
+    }
+    print_corruptions(trace_file, &parser, color);
+    if tracker.is_anonymous() {
+        bail!(
+            "`{}` has no block addresses, enable the extended heap trace in the firmware",
+            trace_file.display()
         );
+    }
+    let leaks = tracker.leaks();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " OUTSTANDING BLOCKS "), Cyan));
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Address"),
+        r->color.bold("Size"),
+        r->color.bold("Packet"),
+        color.bold("Caller"),
+    ]);
+    for block in &leaks {
+        let caller = block.pc.map_or_else(String::new, |pc| {
+            symbols.lookup(pc).unwrap_or_else(|| format!("0x{:08X}", pc))
+        });
+        table.add_row(row![
+            r->format!("0x{:08X}", block.addr),
+            r->block.size,
+            r->block.packet,
+            caller,
+        ]);
+    }
+    table.print(&mut stderr())?;
+    eprintln!();
+    eprintln!(
+        "Outstanding blocks: {}",
+        color.bold(&format!(
+            "{} / {} bytes",
+            leaks.len(),
+            leaks.iter().map(|block| u64::from(block.size)).sum::<u64>()
+        ))
+    );
+    eprintln!();
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " BLOCK LIFETIMES (PACKETS) "), Cyan));
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Block Size"),
+        r->color.bold("Freed"),
+        r->color.bold("Outstanding"),
+        r->color.bold("Min"),
+        r->color.bold("Median"),
+        r->color.bold("90%"),
+        r->color.bold("Max"),
+    ]);
+    for lifetimes in tracker.lifetimes() {
+        let lifetime = |value: usize| {
+            if lifetimes.freed == 0 { "-".to_string() } else { value.to_string() }
+        };
+        table.add_row(row![
+            r->format_size(u64::from(lifetimes.size)),
+            r->lifetimes.freed,
+            r->lifetimes.outstanding,
+            r->lifetime(lifetimes.min),
+            r->lifetime(lifetimes.median),
+            r->lifetime(lifetimes.p90),
+            r->lifetime(lifetimes.max),
+        ]);
+    }
+    table.print(&mut stderr())?;
+    if tracker.anonymous > 0 {
         eprintln!(
-            "# {}: replace the existing [heap.{}] section in Drone.toml",
-            color.bold_fg("hint", Cyan),
-            config
+            "{}: {} packets without block addresses were ignored",
+            color.bold_fg("warning", Yellow),
+            tracker.anonymous
+        );
+    }
+    if tracker.unmatched > 0 {
+        eprintln!(
+            "{}: {} packets refer to unknown blocks",
+            color.bold_fg("warning", Yellow),
+            tracker.unmatched
         );
     }
     Ok(())
 }
 
-fn print_table(trace: &TraceMap, size: u32, color: Color) -> Result<()> {
+/// Runs `drone heap report` command.
+pub async fn report(
+    cmd: HeapReportCmd,
+    package: &PackageOpts,
+    trace_file: &Path,
+    heap_config: &str,
+    size: Option<u64>,
+    lenient: bool,
+    color: Color,
+) -> Result<()> {
+    let HeapReportCmd { html } = cmd;
+    let config = read_config(package).await?;
+    let block = heap_block(&config, heap_config)?;
+    let size = size.unwrap_or(block.size);
+    let mut parser = Parser::new(heap::open_trace(trace_file)?, lenient)?;
+    let packets = parser.by_ref().collect::<Result<Vec<_>, _>>()?;
+    print_corruptions(trace_file, &parser, color);
+    let report = Report::new(heap_config, block, size, &packets, lenient)?;
+    fs::write(&html, report.render()?)
+        .map_err(|err| anyhow!("Couldn't write `{}`: {}", html.display(), err))?;
+    eprintln!("     {} {}", color.bold_fg("Created", Green), html.display());
+    Ok(())
+}
+
+/// Runs `drone heap simulate` command.
+pub async fn simulate(
+    cmd: HeapSimulateCmd,
+    package: &PackageOpts,
+    trace_file: &Path,
+    heap_config: &str,
+    lenient: bool,
+    color: Color,
+) -> Result<()> {
+    let HeapSimulateCmd {} = cmd;
+    let config = read_config(package).await?;
+    let block = heap_block(&config, heap_config)?;
+    let mut parser = Parser::new(heap::open_trace(trace_file)?, lenient)?;
+    let allocator = heap::simulate::simulate(&mut parser, &block.pools)?;
+    print_corruptions(trace_file, &parser, color);
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", " SIMULATION "), Cyan));
+    print_pools(&allocator, color)?;
+    Ok(())
+}
+
+/// Runs `drone heap check` command.
+pub async fn check(
+    cmd: HeapCheckCmd,
+    package: &PackageOpts,
+    trace_file: PathBuf,
+    heap_config: &str,
+    size: Option<u64>,
+    lenient: bool,
+) -> Result<()> {
+    let HeapCheckCmd { mut trace_files, max_fragmentation } = cmd;
+    let config = read_config(package).await?;
+    let block = heap_block(&config, heap_config)?;
+    if trace_files.is_empty() {
+        trace_files.push(trace_file);
+    }
+    let report = check_report(heap_config, block, trace_files, size, max_fragmentation, lenient)?;
+    print_check(&report)
+}
+
+fn check_report<'a>(
+    heap_config: &'a str,
+    block: &HeapBlock,
+    trace_files: Vec<PathBuf>,
+    size: Option<u64>,
+    max_fragmentation: Option<f64>,
+    lenient: bool,
+) -> Result<CheckReport<'a>> {
+    let size = size.unwrap_or(block.size);
+    let max_fragmentation = max_fragmentation.or(block.max_fragmentation);
+    let layout = block.pools.iter().map(|pool| (pool.block, pool.capacity)).collect::<Vec<_>>();
+    let mut traces = Vec::with_capacity(trace_files.len());
+    for file in trace_files {
+        let mut parser = Parser::new(heap::open_trace(&file)?, lenient)?;
+        let mut trace = TraceMap::new();
+        let mut load = Load::default();
+        let mut allocator = Allocator::new(&block.pools);
+        for packet in &mut parser {
+            if let Some(packet) = heap::replay(&mut trace, &mut load, packet?, size, lenient)? {
+                allocator.apply(&packet);
+            }
+        }
+        let fragmentation = heap::layout::fragmentation(&trace, &layout, Occupancy::Max);
+        let fragmentation_percent = fragmentation as f64 / size as f64 * 100.0;
+        let passed = allocator.failure_count == 0
+            && max_fragmentation.map_or(true, |max| fragmentation_percent <= max);
+        let pools = allocator
+            .pools
+            .iter()
+            .map(|pool| PoolReport { load: pool.clone(), headroom: pool.headroom() })
+            .collect();
+        traces.push(TraceReport {
+            file,
+            passed,
+            peak: load.peak,
+            peak_packet: load.peak_packet,
+            fragmentation,
+            fragmentation_percent,
+            failed_allocations: allocator.failure_count,
+            failures: allocator.failures,
+            pools,
+            skipped: parser.skipped(),
+            corruptions: parser.corruptions().to_vec(),
+            skipped_packets: load.skipped_packets,
+        });
+    }
+    let passed = traces.iter().all(|trace| trace.passed);
+    Ok(CheckReport { heap: heap_config, size, max_fragmentation, passed, traces })
+}
+
+/// Prints `report` as JSON and fails if the check didn't pass.
+fn print_check(report: &CheckReport<'_>) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(report)?);
+    if !report.passed {
+        bail!("Heap check failed");
+    }
+    Ok(())
+}
+
+/// Runs `drone heap live` command.
+pub async fn live(
+    cmd: HeapLiveCmd,
+    package: &PackageOpts,
+    heap_config: &str,
+    size: Option<u64>,
+    color: Color,
+) -> Result<()> {
+    let HeapLiveCmd { reset, port } = cmd;
+    let signals = register_signals()?;
+    let registry = Registry::new()?;
+    let config = read_config(package).await?;
+    let probe = Probe::try_from(&config)?;
+    let log = Log::try_from(&config)?;
+    let block = heap_block(&config, heap_config)?;
+    let size = size.unwrap_or(block.size);
+    let port = port.or(block.trace_port).unwrap_or(DEFAULT_TRACE_PORT);
+    let (sender, receiver) = mpsc::channel();
+    let mut outputs = OutputMap::new(&[])?;
+    outputs.push(vec![port], OutputStream::Channel(sender));
+    let state = heap::live::spawn(receiver, size, &block.pools);
+    let refresh = tokio::spawn(refresh_live(state, size, color));
+    let result =
+        probe::log(probe, log, reset, outputs, None, signals, registry, config, color).await;
+    refresh.abort();
+    result.ok_or_else(|| {
+        anyhow!(
+            "`{}` log with `{}` probe is not supported",
+            ser_to_string(probe),
+            ser_to_string(log)
+        )
+    })?
+}
+
+async fn refresh_live(state: Arc<Mutex<LiveTrace>>, size: u64, color: Color) {
+    let mut interval = time::interval(LIVE_REFRESH);
+    let mut shown = None;
+    loop {
+        interval.tick().await;
+        let state = state.lock().unwrap();
+        if shown == Some(state.load.packets) {
+            continue;
+        }
+        shown = Some(state.load.packets);
+        eprint!("{}", color.clear_screen());
+        eprintln!("{}", color.bold_fg(&format!("{:=^80}", " LIVE HEAP TRACE "), Cyan));
+        if let Err(err) = print_table(&state.trace, &state.load, size, color) {
+            log::error!("{}", err);
+        }
+        if !state.allocator.pools.is_empty() {
+            eprintln!();
+            if let Err(err) = print_pools(&state.allocator, color) {
+                log::error!("{}", err);
+            }
+        }
+        eprintln!();
+        eprintln!("Packets processed: {}", state.load.packets);
+        if state.skipped > 0 {
+            eprintln!("Corrupted bytes skipped: {}", state.skipped);
+        }
+        if state.load.skipped_packets > 0 {
+            eprintln!("Packets of unknown blocks skipped: {}", state.load.skipped_packets);
+        }
+        if let Some(err) = &state.error {
+            eprintln!("{}: trace processing stopped: {}", color.bold_fg("error", Red), err);
+        }
+    }
+}
+
+fn print_corruptions(trace_file: &Path, parser: &Parser, color: Color) {
+    let corruptions = parser.corruptions();
+    for Corruption { offset, len } in corruptions.iter().take(MAX_CORRUPTIONS) {
+        eprintln!(
+            "{}: `{}`: skipped {} corrupted bytes at offset {:#x}",
+            color.bold_fg("warning", Yellow),
+            trace_file.display(),
+            len,
+            offset
+        );
+    }
+    if corruptions.len() > MAX_CORRUPTIONS {
+        eprintln!(
+            "{}: `{}`: skipped {} corrupted bytes in {} regions in total",
+            color.bold_fg("warning", Yellow),
+            trace_file.display(),
+            parser.skipped(),
+            corruptions.len()
+        );
+    }
+}
+
+fn print_skipped_packets(trace_file: &Path, load: &Load, color: Color) {
+    if load.skipped_packets > 0 {
+        eprintln!(
+            "{}: `{}`: skipped {} packets which don't match the trace",
+            color.bold_fg("warning", Yellow),
+            trace_file.display(),
+            load.skipped_packets
+        );
+    }
+}
+
+async fn read_symbols(firmware: &Path) -> Result<Symbols> {
+    let mut nm = Command::new(search_rust_tool("llvm-nm").await?);
+    nm.arg("--print-size").arg("--demangle").arg("--defined-only").arg(firmware);
+    let output = nm.output().await?;
+    if !output.status.success() {
+        bail!(
+            "Couldn't read symbols of `{}`: {}",
+            firmware.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Symbols::parse(&String::from_utf8(output.stdout)?))
+}
+
+/// Returns every configured heap, `main` first, then the rest sorted by key.
+fn heaps(config: &config::Config) -> Vec<(&str, &HeapBlock)> {
+    let mut heaps = vec![("main", &config.heap.main)];
+    let mut extra =
+        config.heap.extra.iter().map(|(key, heap)| (key.as_str(), &heap.block)).collect::<Vec<_>>();
+    extra.sort_by_key(|(key, _)| *key);
+    heaps.extend(extra);
+    heaps
+}
+
+/// Returns the unused part of `size` in percents.
+fn headroom(used: u64, size: u64) -> f64 {
+    if size == 0 { 0.0 } else { (size as f64 - used as f64) / size as f64 * 100.0 }
+}
+
+fn heap_block<'a>(config: &'a config::Config, heap_config: &str) -> Result<&'a HeapBlock> {
+    if heap_config == "main" {
+        Ok(&config.heap.main)
+    } else {
+        config
+            .heap
+            .extra
+            .get(heap_config)
+            .map(|heap| &heap.block)
+            .ok_or_else(|| anyhow!("Unknown `{}` heap configuration", heap_config))
+    }
+}
+
+fn print_table(trace: &TraceMap, load: &Load, size: u64, color: Color) -> Result<()> {
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     table.set_titles(row![
         r->color.bold("Block Size"),
+        r->color.bold("Current Load"),
         r->color.bold("Max Load"),
+        r->color.bold("Peak Load"),
         r->color.bold("Total Allocations"),
     ]);
     let mut used = 0;
     for (size, entry) in trace {
         table.add_row(row![
-            r->format_size(*size),
+            r->format_size(u64::from(*size)),
+            r->entry.cur,
             r->entry.max,
+            r->entry.peak,
             r->entry.total,
         ]);
-        used += size * entry.max;
+        used += u64::from(*size) * u64::from(entry.max);
     }
     table.print(&mut stderr())?;
     eprintln!();
     eprintln!(
-        "Maximum heap load: {}",
-        color.bold(&format!("{} / {:.2}%", used, f64::from(used) / f64::from(size) * 100.0))
+        "Peak heap load: {} at packet {}",
+        color.bold(&format!("{} / {:.2}%", load.peak, load.peak as f64 / size as f64 * 100.0)),
+        load.peak_packet
+    );
+    eprintln!(
+        "Sum of maximum loads: {}",
+        color.bold(&format!("{} / {:.2}%", used, used as f64 / size as f64 * 100.0))
     );
     Ok(())
 }
+
+fn print_curve(curve: &[(u32, u64)], selected: u32, size: u64, color: Color) -> Result<()> {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![r->color.bold("Pools"), r->color.bold("Fragmentation")]);
+    for &(pools, frag) in curve {
+        let frag = format!("{} / {:.2}%", frag, frag as f64 / size as f64 * 100.0);
+        if pools == selected {
+            table.add_row(row![r->color.bold(&pools.to_string()), r->color.bold(&frag)]);
+        } else {
+            table.add_row(row![r->pools, r->frag]);
+        }
+    }
+    table.print(&mut stderr())?;
+    Ok(())
+}
+
+fn print_pools(allocator: &Allocator, color: Color) -> Result<()> {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        r->color.bold("Pool Block"),
+        r->color.bold("Capacity"),
+        r->color.bold("Current Load"),
+        r->color.bold("Max Load"),
+        r->color.bold("Headroom"),
+        r->color.bold("Wasted Bytes"),
+    ]);
+    for pool in &allocator.pools {
+        let headroom = format!("{:.2}%", pool.headroom());
+        let headroom =
+            if pool.max == pool.capacity { color.bold_fg(&headroom, Red) } else { headroom };
+        table.add_row(row![
+            r->format_size(pool.block),
+            r->pool.capacity,
+            r->pool.cur,
+            r->pool.max,
+            r->headroom,
+            r->pool.max_waste,
+        ]);
+    }
+    table.print(&mut stderr())?;
+    eprintln!();
+    if allocator.failure_count == 0 {
+        eprintln!("Failed allocations: {}", color.bold("0"));
+    } else {
+        eprintln!(
+            "Failed allocations: {}",
+            color.bold_fg(&allocator.failure_count.to_string(), Red)
+        );
+        for failure in &allocator.failures {
+            eprintln!("    packet {}: {} bytes", failure.packet, failure.size);
+        }
+        if allocator.failure_count > allocator.failures.len() {
+            eprintln!("    ... and {} more", allocator.failure_count - allocator.failures.len());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::trace::KEY;
+    use serde_json::json;
+    use tempfile::NamedTempFile;
+
+    fn trace(frames: &[[u8; 4]]) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let bytes = frames
+            .iter()
+            .flat_map(|frame| (u32::from_be_bytes(*frame) ^ KEY).to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        fs::write(file.path(), bytes).unwrap();
+        file
+    }
+
+    fn block() -> HeapBlock {
+        toml::from_str(
+            r#"
+size = "64"
+pools = [{ block = "16", capacity = 2 }, { block = "32", capacity = 1 }]
+"#,
+        )
+        .unwrap()
+    }
+
+    fn pools(cur: [u32; 2], waste: u64) -> serde_json::Value {
+        json!([
+            {
+                "block": 16,
+                "capacity": 2,
+                "cur": cur[0],
+                "max": cur[0],
+                "waste": waste,
+                "max_waste": waste,
+                "headroom": if cur[0] == 0 { 100.0 } else { 0.0 },
+            },
+            {
+                "block": 32,
+                "capacity": 1,
+                "cur": cur[1],
+                "max": cur[1],
+                "waste": 0,
+                "max_waste": 0,
+                "headroom": 100.0,
+            },
+        ])
+    }
+
+    fn fitting() -> NamedTempFile {
+        trace(&[[0xA1, 0, 0, 0], [0xA2, 0, 0, 16], [0xA1, 0, 0, 0], [0xA2, 0, 0, 12]])
+    }
+
+    fn oversized() -> NamedTempFile {
+        trace(&[[0xA1, 0, 0, 0], [0xA2, 0, 0, 48], [0xD1, 0, 0, 0], [0xD2, 0, 0, 48]])
+    }
+
+    #[test]
+    fn check_pass() {
+        let fitting = fitting();
+        let files = vec![fitting.path().to_path_buf()];
+        let report = check_report("main", &block(), files, None, None, false).unwrap();
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "heap": "main",
+                "size": 64,
+                "max_fragmentation": null,
+                "passed": true,
+                "traces": [{
+                    "file": fitting.path(),
+                    "passed": true,
+                    "peak": 28,
+                    "peak_packet": 1,
+                    "fragmentation": 4,
+                    "fragmentation_percent": 6.25,
+                    "failed_allocations": 0,
+                    "failures": [],
+                    "pools": pools([2, 0], 4),
+                    "skipped": 0,
+                    "corruptions": [],
+                    "skipped_packets": 0,
+                }],
+            })
+        );
+        assert!(print_check(&report).is_ok());
+    }
+
+    #[test]
+    fn check_fail() {
+        let (fitting, oversized) = (fitting(), oversized());
+        let files = vec![fitting.path().to_path_buf(), oversized.path().to_path_buf()];
+        let report = check_report("main", &block(), files, None, Some(10.0), false).unwrap();
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["max_fragmentation"], json!(10.0));
+        assert_eq!(json["passed"], false);
+        assert_eq!(json["traces"][0]["passed"], true);
+        assert_eq!(
+            json["traces"][1],
+            json!({
+                "file": oversized.path(),
+                "passed": false,
+                "peak": 48,
+                "peak_packet": 0,
+                "fragmentation": 0,
+                "fragmentation_percent": 0.0,
+                "failed_allocations": 1,
+                "failures": [{ "packet": 0, "size": 48 }],
+                "pools": pools([0, 0], 0),
+                "skipped": 0,
+                "corruptions": [],
+                "skipped_packets": 0,
+            })
+        );
+        assert_eq!(print_check(&report).unwrap_err().to_string(), "Heap check failed");
+    }
+
+    #[test]
+    fn check_max_fragmentation() {
+        let fitting = fitting();
+        let files = vec![fitting.path().to_path_buf()];
+        let report = check_report("main", &block(), files, None, Some(5.0), false).unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.traces[0].failed_allocations, 0);
+        assert!(print_check(&report).is_err());
+    }
+}
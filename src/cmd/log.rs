@@ -1,32 +1,53 @@
 //! `drone log` command.
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fs::File};
 
 use anyhow::{anyhow, Result};
 
-use drone_config as config;
-
 use crate::{
-    cli::LogCmd,
+    cli::{LogCmd, PackageOpts},
     color::Color,
+    log,
+    log::{InputSpec, LogSource, OutputMap, Recorder},
     probe,
     probe::{Log, Probe},
     templates::Registry,
-    utils::{register_signals, ser_to_string},
+    utils::{read_config, register_signals, ser_to_string},
 };
 
 /// Runs `drone log` command.
-pub async fn run(cmd: LogCmd, color: Color) -> Result<()> {
-    let signals = register_signals()?;
+pub async fn run(cmd: LogCmd, package: &PackageOpts, color: Color) -> Result<()> {
+    let LogCmd { reset, input, record, replay, outputs } = cmd;
+    let mut signals = register_signals()?;
     let registry = Registry::new()?;
-    let config = config::Config::read_from_current_dir()?;
-    let probe = Probe::try_from(&config)?;
+    let config = read_config(package).await?;
     let log = Log::try_from(&config)?;
-    probe::log(probe, log, cmd, signals, registry, config, color).await.ok_or_else(|| {
-        anyhow!(
-            "`{}` log with `{}` probe is not supported",
-            ser_to_string(probe),
-            ser_to_string(log)
-        )
-    })?
+    let outputs = OutputMap::new(&outputs)?;
+    if let Some(replay) = replay {
+        let recording = File::open(&replay)
+            .map_err(|err| anyhow!("Couldn't open `{}`: {}", replay.display(), err))?;
+        return log::replay(recording, outputs, log::parser(log), color);
+    }
+    let recorder = record.as_deref().map(Recorder::create).transpose()?;
+    if let Some(InputSpec { log: input_log, source: mut input }) = input {
+        let log = input_log.unwrap_or(log);
+        if let LogSource::Serial { baud_rate: baud_rate @ None, .. } = &mut input {
+            *baud_rate = config.log.as_ref().and_then(|config_log| match log {
+                Log::SwoProbe | Log::SwoSerial => config_log.swo.as_ref().map(|swo| swo.baud_rate),
+                Log::DsoSerial => config_log.dso.as_ref().map(|dso| dso.baud_rate),
+            });
+        }
+        let capture = log::capture(input, outputs, log::parser(log), recorder, color).await?;
+        return capture.wait(&mut signals).await;
+    }
+    let probe = Probe::try_from(&config)?;
+    probe::log(probe, log, reset, outputs, recorder, signals, registry, config, color)
+        .await
+        .ok_or_else(|| {
+            anyhow!(
+                "`{}` log with `{}` probe is not supported",
+                ser_to_string(probe),
+                ser_to_string(log)
+            )
+        })?
 }
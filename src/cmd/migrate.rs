@@ -0,0 +1,527 @@
+//! `drone migrate` command.
+
+use crate::{
+    cli::{MigrateCmd, PackageOpts},
+    color::Color,
+    crates,
+    devices::{Device, REGISTRY},
+    edit::ConfigFile,
+    templates::Registry,
+    utils::{cargo_config_path, crate_root, resolve_target},
+};
+use ansi_term::Color::{Cyan, Green, Red, Yellow};
+use anyhow::{anyhow, Result};
+use drone_config::CONFIG_NAME;
+use std::{
+    fs,
+    io::{stdin, stdout, Write},
+    mem,
+    path::Path,
+};
+use toml_edit::{decorated, Document, Item, Table};
+
+/// Number of unchanged lines shown around each change.
+const DIFF_CONTEXT: usize = 2;
+
+/// Runs `drone migrate` command.
+pub async fn run(cmd: MigrateCmd, package: &PackageOpts, color: Color) -> Result<()> {
+    let MigrateCmd { yes } = cmd;
+    let crate_root = crate_root(package).await?;
+    let registry = Registry::new()?;
+    let target = resolve_target(&crate_root).await?;
+    let cargo_config_path = cargo_config_path(&crate_root).await?;
+    let cargo_config_source = fs::read_to_string(&cargo_config_path).unwrap_or_default();
+    let device = find_device(&target, &cargo_config_source);
+    let platform = device.map_or_else(
+        || if target.starts_with("riscv") { "riscv" } else { "arm" },
+        |device| device.platform_crate.linker_platform(),
+    );
+
+    let drone_toml = drone_toml(&crate_root, platform, package.board.as_deref())?;
+    patch(&crate_root, &crate_root.join(CONFIG_NAME), &drone_toml, yes, color)?;
+    let cargo_toml = cargo_toml(&crate_root)?;
+    patch(&crate_root, &crate_root.join("Cargo.toml"), &cargo_toml, yes, color)?;
+    if let Some(device) = device {
+        let template = registry.new_cargo_config(device)?;
+        let cargo_config = cargo_config(&cargo_config_source, &template)?;
+        patch(&crate_root, &cargo_config_path, &cargo_config, yes, color)?;
+    } else {
+        eprintln!(
+            "{}: couldn't detect the target device, skipping `.cargo/config`",
+            color.bold_fg("warning", Yellow),
+        );
+    }
+    let justfile_path = crate_root.join("Justfile");
+    let current = fs::read_to_string(&justfile_path).ok();
+    let (justfile, obsolete) = justfile(current.as_deref(), &registry.new_justfile()?);
+    for name in obsolete {
+        eprintln!(
+            "{}: recipe `{}` in `Justfile` calls `drone env`, which was removed",
+            color.bold_fg("warning", Yellow),
+            name
+        );
+    }
+    patch(&crate_root, &justfile_path, &justfile, yes, color)?;
+    Ok(())
+}
+
+/// Finds the device the project was generated for by its target triple and
+/// the bindings flag in `.cargo/config`.
+fn find_device(target: &str, cargo_config: &str) -> Option<&'static Device> {
+    let candidates = REGISTRY.iter().filter(|device| device.target == target).collect::<Vec<_>>();
+    let flagged = candidates
+        .iter()
+        .copied()
+        .filter(|device| {
+            let flag = device.bindings_crate.krate.flag_name();
+            cargo_config.contains(&format!("{}=\"{}\"", flag, device.bindings_crate.flag))
+        })
+        .collect::<Vec<_>>();
+    match (flagged.as_slice(), candidates.as_slice()) {
+        ([device], _) | ([], [device]) => Some(device),
+        _ => None,
+    }
+}
+
+/// Upgrades `Drone.toml` to the current schema.
+fn drone_toml(crate_root: &Path, platform: &str, board: Option<&str>) -> Result<String> {
+    let mut file = ConfigFile::open(crate_root, false, board)?;
+    // 0.13: `linker.platform` became mandatory.
+    if file.get("linker.platform").is_none() {
+        file.set("linker.platform", platform)?;
+    }
+    // 0.14: the heap moved to `[heap.main]`. The table is moved as a whole
+    // to keep its comments and position.
+    if file.get("heap.size").is_some() {
+        let main = file.take("heap")?;
+        file.replace("heap.main", main)?;
+    }
+    file.render()
+}
+
+/// Updates the `drone-*` dependency versions in `Cargo.toml`.
+fn cargo_toml(crate_root: &Path) -> Result<String> {
+    let path = crate_root.join("Cargo.toml");
+    let mut document = fs::read_to_string(&path)?
+        .parse::<Document>()
+        .map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+    let names = document
+        .as_table()
+        .get("dependencies")
+        .and_then(Item::as_table_like)
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .filter(|name| name.starts_with("drone-"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    for name in names {
+        let dependency = &mut document["dependencies"][&name];
+        if dependency.as_str().is_some() {
+            set_version(dependency);
+        } else if dependency.as_table_like().and_then(|table| table.get("version")).is_some() {
+            set_version(&mut dependency["version"]);
+        }
+    }
+    Ok(document.to_string())
+}
+
+fn set_version(item: &mut Item) {
+    if let Some(current) = item.as_value() {
+        let decor = current.decor();
+        *item = Item::Value(decorated(crates::VERSION.into(), decor.prefix(), decor.suffix()));
+    }
+}
+
+/// Sets the keys defined by the `.cargo/config` template, keeping the rest of
+/// `current` intact.
+fn cargo_config(current: &str, template: &str) -> Result<String> {
+    let mut document =
+        current.parse::<Document>().map_err(|err| anyhow!("`.cargo/config`: {}", err))?;
+    let template = template.parse::<Document>()?;
+    for (key, item) in template.as_table().iter() {
+        merge_owned(&mut document[key], item);
+    }
+    Ok(document.to_string())
+}
+
+fn merge_owned(item: &mut Item, template: &Item) {
+    match template.as_table() {
+        Some(template) if item.is_table() || item.is_none() => {
+            if item.is_none() {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                *item = Item::Table(table);
+            }
+            for (key, value) in template.iter() {
+                merge_owned(&mut item[key], value);
+            }
+        }
+        _ => *item = template.clone(),
+    }
+}
+
+/// Replaces the variables and recipes defined by the `Justfile` template,
+/// keeping the user's own ones intact. Missing variables are appended, as the
+/// template recipes may use them. Missing recipes are not, as they may have
+/// been deleted deliberately.
+///
+/// Also returns the names of the kept recipes which call the removed `drone
+/// env` command.
+fn justfile(current: Option<&str>, template: &str) -> (String, Vec<String>) {
+    let mut items = match current {
+        Some(current) => justfile_items(current),
+        None => return (template.to_string(), Vec::new()),
+    };
+    let mut replaced = Vec::new();
+    for (name, text) in justfile_items(template) {
+        let name = match name {
+            Some(name) => name,
+            None => continue,
+        };
+        if let Some(item) = items.iter_mut().find(|(item, _)| item.as_ref() == Some(&name)) {
+            item.1 = text;
+            replaced.push(name);
+        } else if is_justfile_variable(&text) {
+            if items.last().map_or(false, |(_, text)| !text.trim().is_empty()) {
+                items.push((None, "\n".to_string()));
+            }
+            items.push((Some(name), text));
+        }
+    }
+    let obsolete = items
+        .iter()
+        .filter(|(_, text)| !is_justfile_variable(text) && text.contains("drone env"))
+        .filter_map(|(name, _)| name.clone())
+        .filter(|name| !replaced.contains(name))
+        .collect();
+    (items.into_iter().map(|(_, text)| text).collect(), obsolete)
+}
+
+fn is_justfile_variable(text: &str) -> bool {
+    text.lines().find(|line| !line.starts_with('#')).map_or(false, |line| line.contains(":="))
+}
+
+/// Splits a `Justfile` into variable assignments and recipes along with their
+/// leading comments, keyed by their names, and unnamed lines in between.
+fn justfile_items(source: &str) -> Vec<(Option<String>, String)> {
+    let mut items = Vec::new();
+    let mut comments = String::new();
+    let mut lines = source.lines().peekable();
+    while let Some(line) = lines.next() {
+        if line.starts_with('#') {
+            comments.push_str(line);
+            comments.push('\n');
+            continue;
+        }
+        let mut text = mem::take(&mut comments);
+        text.push_str(line);
+        text.push('\n');
+        let name = justfile_item_name(line);
+        if name.is_some() {
+            while let Some(line) = lines.next_if(|line| line.starts_with(char::is_whitespace)) {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        items.push((name, text));
+    }
+    if !comments.is_empty() {
+        items.push((None, comments));
+    }
+    items
+}
+
+fn justfile_item_name(line: &str) -> Option<String> {
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let line = line.strip_prefix('@').unwrap_or(line);
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let end = line.find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_')?;
+    Some(line[..end].to_string()).filter(|name| !name.is_empty())
+}
+
+/// Shows the difference between the current contents of `path` and
+/// `contents`, and writes `contents` after confirmation.
+fn patch(crate_root: &Path, path: &Path, contents: &str, yes: bool, color: Color) -> Result<()> {
+    let name = path.strip_prefix(crate_root).unwrap_or(path).display().to_string();
+    let current = fs::read_to_string(path).unwrap_or_default();
+    if current == contents {
+        return Ok(());
+    }
+    eprintln!("{}", color.bold_fg(&format!("{:=^80}", format!(" {} ", name)), Cyan));
+    print_diff(&current, contents, color);
+    if !yes && !confirm(&format!("Apply changes to `{}`?", name))? {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    eprintln!("     {} {}", color.bold_fg("Patched", Green), name);
+    Ok(())
+}
+
+fn print_diff(old: &str, new: &str, color: Color) {
+    let lines = diff::lines(old, new);
+    let changed = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, diff::Result::Both(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let mut last = None;
+    for (i, line) in lines.iter().enumerate() {
+        let near = changed.iter().any(|&c| c + DIFF_CONTEXT >= i && c <= i + DIFF_CONTEXT);
+        if !near {
+            continue;
+        }
+        if last.map_or(i > 0, |last| i > last + 1) {
+            eprintln!("{}", color.bold_fg("...", Cyan));
+        }
+        last = Some(i);
+        match line {
+            diff::Result::Left(line) => eprintln!("{}", color.bold_fg(&format!("-{}", line), Red)),
+            diff::Result::Right(line) => {
+                eprintln!("{}", color.bold_fg(&format!("+{}", line), Green));
+            }
+            diff::Result::Both(line, _) => eprintln!(" {}", line),
+        }
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn crate_root(drone_toml: &str) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(CONFIG_NAME), drone_toml).unwrap();
+        dir
+    }
+
+    #[test]
+    fn drone_toml_moves_heap() {
+        let dir = crate_root(
+            "\
+# Memory layout
+[memory.flash]
+size = \"1M\"
+origin = 0x08000000
+
+[memory.ram]
+size = \"128K\" # Without CCM
+origin = 0x20000000
+
+# Main heap
+[heap]
+size = \"64K\"
+pools = [
+    { block = \"4\", capacity = 896 },
+    { block = \"32\", capacity = 1936 }, # DMA buffers
+]
+
+[probe]
+gdb-client-command = \"gdb-multiarch\"
+",
+        );
+        assert_eq!(
+            drone_toml(dir.path(), "arm", None).unwrap(),
+            "\
+# Memory layout
+[memory.flash]
+size = \"1M\"
+origin = 0x08000000
+
+[memory.ram]
+size = \"128K\" # Without CCM
+origin = 0x20000000
+
+# Main heap
+[heap.main]
+size = \"64K\"
+pools = [
+    { block = \"4\", capacity = 896 },
+    { block = \"32\", capacity = 1936 }, # DMA buffers
+]
+
+[probe]
+gdb-client-command = \"gdb-multiarch\"
+
+[linker]
+platform = \"arm\"
+"
+        );
+    }
+
+    #[test]
+    fn drone_toml_inserts_platform() {
+        let dir = crate_root(
+            "\
+[memory.flash]
+size = \"1M\"
+origin = 0x08000000
+
+[memory.ram]
+size = \"128K\"
+origin = 0x20000000
+
+[heap.main]
+size = \"0\"
+pools = []
+
+[linker]
+include = [\"layout.ld\"] # Custom sections
+",
+        );
+        assert_eq!(
+            drone_toml(dir.path(), "riscv", None).unwrap(),
+            "\
+[memory.flash]
+size = \"1M\"
+origin = 0x08000000
+
+[memory.ram]
+size = \"128K\"
+origin = 0x20000000
+
+[heap.main]
+size = \"0\"
+pools = []
+
+[linker]
+include = [\"layout.ld\"] # Custom sections
+platform = \"riscv\"
+"
+        );
+        let migrated = drone_toml(dir.path(), "riscv", None).unwrap();
+        fs::write(dir.path().join(CONFIG_NAME), &migrated).unwrap();
+        assert_eq!(drone_toml(dir.path(), "arm", None).unwrap(), migrated);
+    }
+
+    #[test]
+    fn cargo_config_keeps_own_keys() {
+        let current = "\
+[build]
+target = 'thumbv7m-none-eabi'
+jobs = 4 # Own key
+
+[alias]
+b = 'build --release'
+";
+        let template = "\
+[build]
+target = 'thumbv7em-none-eabihf'
+
+[target.thumbv7em-none-eabihf]
+rustflags = ['-C', 'linker=drone-ld']
+";
+        assert_eq!(
+            cargo_config(current, template).unwrap(),
+            "\
+[build]
+target = 'thumbv7em-none-eabihf'
+jobs = 4 # Own key
+
+[alias]
+b = 'build --release'
+
+[target.thumbv7em-none-eabihf]
+rustflags = ['-C', 'linker=drone-ld']
+"
+        );
+    }
+
+    #[test]
+    fn justfile_keeps_own_recipes() {
+        let current = "\
+features := ''
+board := 'disco'
+
+# Build the binary
+build:
+\tcargo build --release
+
+# Flash the board
+@flash-board: build
+\tdrone --board {{board}} flash
+";
+        let template = "\
+features := ''
+name := `basename \"$(pwd)\"`
+
+# Build the binary
+build:
+\tcargo build --features \"{{features}}\" --release
+
+# Assert the reset signal
+reset:
+\tdrone reset
+";
+        let (justfile, obsolete) = justfile(Some(current), template);
+        assert_eq!(
+            justfile,
+            "\
+features := ''
+board := 'disco'
+
+# Build the binary
+build:
+\tcargo build --features \"{{features}}\" --release
+
+# Flash the board
+@flash-board: build
+\tdrone --board {{board}} flash
+
+name := `basename \"$(pwd)\"`
+"
+        );
+        assert!(obsolete.is_empty());
+        assert_eq!(super::justfile(None, template), (template.to_string(), Vec::new()));
+        assert_eq!(super::justfile(Some(template), template), (template.to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn justfile_reports_drone_env() {
+        let current = "\
+# Print the environment
+env:
+\tdrone env {{target}}
+
+# Build the binary
+build:
+\tdrone env -- cargo build --release
+";
+        let template = "\
+# Build the binary
+build:
+\tcargo build --release
+";
+        let (justfile, obsolete) = justfile(Some(current), template);
+        assert_eq!(
+            justfile,
+            "\
+# Print the environment
+env:
+\tdrone env {{target}}
+
+# Build the binary
+build:
+\tcargo build --release
+"
+        );
+        assert_eq!(obsolete, ["env"]);
+    }
+}
@@ -56,6 +56,7 @@ pub async fn run(cmd: NewCmd, color: Color) -> Result<()> {
     src_tasks_root_rs(&path, device, &registry, color)?;
     cargo_toml(&path, &name, device, &registry, color)?;
     drone_toml(&path, device, flash_size, ram_size, &heap, probe, log, &registry, color)?;
+    build_rs(&path, &registry, color)?;
     justfile(&path, &registry, color)?;
     rust_toolchain(&path, &toolchain, &registry, color)?;
     cargo_config(&path, device, &registry, color)?;
@@ -100,7 +101,7 @@ fn choose_probe_and_log(
         .ok_or_else(|| anyhow!("No supported probe and log combination for the given criteria"))
 }
 
-fn new_heap(size: u32, pools: u32) -> Result<String> {
+fn new_heap(size: u64, pools: u32) -> Result<String> {
     let layout = heap::layout::empty(size, pools);
     let mut output = Vec::new();
     heap::layout::render(&mut output, "main", &layout)?;
@@ -202,8 +203,8 @@ fn cargo_toml(
 fn drone_toml(
     path: &Path,
     device: &Device,
-    flash_size: u32,
-    ram_size: u32,
+    flash_size: u64,
+    ram_size: u64,
     heap: &str,
     probe: Probe,
     log: Log,
@@ -219,6 +220,14 @@ fn drone_toml(
     Ok(())
 }
 
+fn build_rs(path: &Path, registry: &Registry<'_>, color: Color) -> Result<()> {
+    let path = path.join("build.rs");
+    let mut file = File::create(&path)?;
+    file.write_all(registry.new_build_rs()?.as_bytes())?;
+    print_created("build.rs", color);
+    Ok(())
+}
+
 fn justfile(path: &Path, registry: &Registry<'_>, color: Color) -> Result<()> {
     let path = path.join("Justfile");
     let mut file = File::create(&path)?;
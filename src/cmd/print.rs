@@ -1,31 +1,71 @@
 //! `drone print` command.
 
 use crate::{
-    cli::{PrintCmd, PrintSubCmd},
+    cli::{PackageOpts, PrintCmd, PrintSubCmd},
     color::Color,
     devices::{Device, REGISTRY},
     probe,
     probe::{Log, Probe},
+    utils::{crate_root, inline_value, resolve_target},
 };
 use anyhow::{Result};
+use drone_config::{Config, Layers, Origin};
 use prettytable::{cell, format, row, Table};
 use std::{
+    env,
     io::{stdout},
+    path::Path,
 };
 
 // const CARGO_CONFIG_PATH: &str = ".cargo/config";
 /// Runs `drone print` command.
-pub async fn run(cmd: PrintCmd, color: Color) -> Result<()> {
+pub async fn run(cmd: PrintCmd, package: &PackageOpts, color: Color) -> Result<()> {
     let PrintCmd { print_sub_cmd } = cmd;
     match print_sub_cmd {
         PrintSubCmd::Target => {
-            println!("{}", crate::utils::resolve_target().await?);
+            println!("{}", resolve_target(&crate_root(package).await?).await?);
             Ok(())
         },
+        PrintSubCmd::Config => config(&crate_root(package).await?, package.board.as_deref(), color),
+        PrintSubCmd::ConfigSchema => config_schema(),
         PrintSubCmd::SupportedDevices => supported_devices(color),
     }
 }
 
+fn config(crate_root: &Path, board: Option<&str>, color: Color) -> Result<()> {
+    let layers = Layers::read(crate_root, board)?;
+    let current_dir = env::current_dir()?.canonicalize()?;
+    for line in config_lines(&layers, &current_dir, color) {
+        println!("{}", line);
+    }
+    Config::from_layers(&layers)?;
+    Ok(())
+}
+
+/// Renders every merged value with its origin, showing file paths relative to
+/// `current_dir`.
+fn config_lines(layers: &Layers, current_dir: &Path, color: Color) -> Vec<String> {
+    layers
+        .origins
+        .iter()
+        .map(|(key, origin)| {
+            let value = layers.get(key).map_or_else(String::new, inline_value);
+            let origin = match origin {
+                Origin::File(path) => {
+                    path.strip_prefix(current_dir).unwrap_or(path).display().to_string()
+                }
+                Origin::Env(_) => origin.to_string(),
+            };
+            format!("{} = {} # {}", color.bold(key), value, origin)
+        })
+        .collect()
+}
+
+fn config_schema() -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&drone_config::schema())?);
+    Ok(())
+}
+
 fn supported_devices(color: Color) -> Result<()> {
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
@@ -79,3 +119,41 @@ fn probe_cell(probe: Option<Probe>, log_swo: bool, log_dso: bool, color: Color)
         "--".into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use drone_config::{CONFIG_NAME, LOCAL_CONFIG_NAME};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn config_origins() {
+        let dir = TempDir::new().unwrap();
+        let crate_root = dir.path().canonicalize().unwrap();
+        fs::create_dir(crate_root.join("boards")).unwrap();
+        fs::write(
+            crate_root.join("boards/common.toml"),
+            "[memory.ram]\nsize = \"64K\"\norigin = 0x20000000\n\n[linker]\nplatform = \"arm\"\n",
+        )
+        .unwrap();
+        fs::write(
+            crate_root.join(CONFIG_NAME),
+            "extends = \"boards/common.toml\"\n\n[memory.ram]\nsize = \"128K\"\n",
+        )
+        .unwrap();
+        fs::write(crate_root.join(LOCAL_CONFIG_NAME), "[linker]\ninclude = [\"local.ld\"]\n")
+            .unwrap();
+        let layers = Layers::read(&crate_root, None).unwrap();
+        assert_eq!(config_lines(&layers, &crate_root, Color::Never), [
+            "linker.include = [\"local.ld\"] # Drone.local.toml",
+            "linker.platform = \"arm\" # boards/common.toml",
+            "memory.ram.origin = 536870912 # boards/common.toml",
+            "memory.ram.size = \"128K\" # Drone.toml",
+        ]);
+        let lines = config_lines(&layers, &crate_root.join("boards"), Color::Never);
+        let local = crate_root.join(LOCAL_CONFIG_NAME);
+        assert_eq!(lines[0], format!("linker.include = [\"local.ld\"] # {}", local.display()));
+        assert_eq!(lines[1], "linker.platform = \"arm\" # common.toml");
+    }
+}
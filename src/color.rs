@@ -35,6 +35,12 @@ impl Color {
         }
     }
 
+    /// Returns the escape sequence which clears the terminal, if colors are
+    /// enabled.
+    pub fn clear_screen(self) -> &'static str {
+        if self.should_color() { "\x1B[2J\x1B[H" } else { "" }
+    }
+
     fn should_color(self) -> bool {
         match self {
             Self::Always => true,
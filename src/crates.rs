@@ -1,5 +1,8 @@
 //! Supported Drone crates.
 
+/// Version of the Drone crates used by generated projects.
+pub const VERSION: &str = "0.14.0";
+
 /// Drone platform crates.
 #[allow(missing_docs)]
 #[derive(Clone, Copy, Debug)]
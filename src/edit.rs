@@ -0,0 +1,272 @@
+//! Format-preserving editing of the configuration files.
+
+use anyhow::{anyhow, bail, Result};
+use drone_config::{Config, Layers, CONFIG_NAME, LOCAL_CONFIG_NAME};
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+};
+use toml_edit::{decorated, table, Document, Item, TableLike, Value};
+
+/// Configuration file opened for editing.
+///
+/// Comments, ordering, and formatting of untouched parts of the file are
+/// preserved.
+pub struct ConfigFile {
+    crate_root: PathBuf,
+    local: bool,
+    board: Option<String>,
+    document: Document,
+}
+
+impl ConfigFile {
+    /// Opens `Drone.toml` at `crate_root`, or `Drone.local.toml` if `local`
+    /// is `true`. A missing local override file is treated as empty.
+    ///
+    /// The edited configuration is validated with the `board` section
+    /// selected.
+    pub fn open(crate_root: &Path, local: bool, board: Option<&str>) -> Result<Self> {
+        let crate_root = crate_root.to_path_buf();
+        let path = crate_root.join(if local { LOCAL_CONFIG_NAME } else { CONFIG_NAME });
+        let source = if local && !path.exists() {
+            String::new()
+        } else {
+            fs::read_to_string(&path).map_err(|err| anyhow!("{}: {}", path.display(), err))?
+        };
+        let document =
+            source.parse::<Document>().map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        let board = board.map(ToString::to_string);
+        Ok(Self { crate_root, local, board, document })
+    }
+
+    /// Returns the file name.
+    pub fn name(&self) -> &'static str {
+        if self.local { LOCAL_CONFIG_NAME } else { CONFIG_NAME }
+    }
+
+    /// Returns the item at the dotted `key` if present in this file.
+    pub fn get(&self, key: &str) -> Option<&Item> {
+        let mut segments = key.split('.');
+        let first = self.document.as_table().get(segments.next()?)?;
+        segments
+            .try_fold(first, |item, segment| item.as_table_like()?.get(segment))
+            .filter(|item| !item.is_none())
+    }
+
+    /// Sets the dotted `key` to `raw`, creating missing tables.
+    ///
+    /// `raw` is parsed as a TOML value, unless the current effective value is
+    /// a string or `raw` is not a valid TOML value. In these cases it is
+    /// stored as a string. Comments around the current value are kept.
+    pub fn set(&mut self, key: &str, raw: &str) -> Result<()> {
+        let merged_string = Layers::read(&self.crate_root, self.board.as_deref())
+            .map_or(false, |layers| layers.get(key).map_or(false, toml::Value::is_str));
+        let item = self.item_mut(key)?;
+        let value = match raw.parse::<Value>() {
+            Ok(value) if item.as_str().is_none() && !merged_string => value,
+            _ => Value::from(raw),
+        };
+        let value = match item.as_value() {
+            Some(current) => decorated(value, current.decor().prefix(), current.decor().suffix()),
+            None => decorated(value, " ", ""),
+        };
+        *item = Item::Value(value);
+        Ok(())
+    }
+
+    /// Replaces the dotted `key` with `item`, creating missing tables.
+    pub fn replace(&mut self, key: &str, item: Item) -> Result<()> {
+        *self.item_mut(key)? = item;
+        Ok(())
+    }
+
+    /// Removes the dotted `key` along with its parent tables left empty.
+    /// Returns `false` if there was no such key.
+    pub fn unset(&mut self, key: &str) -> Result<bool> {
+        Ok(!self.take(key)?.is_none())
+    }
+
+    /// Removes the dotted `key` along with its parent tables left empty, and
+    /// returns the removed item.
+    pub fn take(&mut self, key: &str) -> Result<Item> {
+        let segments = segments(key)?;
+        let item = match self.get_mut(&segments) {
+            Some(item) => mem::replace(item, Item::None),
+            None => return Ok(Item::None),
+        };
+        for depth in (1..segments.len()).rev() {
+            if let Some(parent) = self.get_mut(&segments[..depth]) {
+                if parent.as_table_like().map_or(false, TableLike::is_empty) {
+                    *parent = Item::None;
+                }
+            }
+        }
+        Ok(item)
+    }
+
+    /// Validates the edited configuration and writes it to the disk.
+    pub fn save(&self) -> Result<()> {
+        let source = self.render()?;
+        fs::write(self.crate_root.join(self.name()), source)?;
+        Ok(())
+    }
+
+    /// Validates the edited configuration and returns its contents.
+    pub fn render(&self) -> Result<String> {
+        let source = self.document.to_string();
+        let main_path = self.crate_root.join(CONFIG_NAME);
+        let layers = if self.local {
+            Layers::parse_with_local(
+                &self.crate_root,
+                &fs::read_to_string(&main_path)?,
+                Some(&source),
+                self.board.as_deref(),
+            )?
+        } else {
+            Layers::parse(&self.crate_root, &source, self.board.as_deref())?
+        };
+        Config::from_layers(&layers)?;
+        Ok(source)
+    }
+
+    fn get_mut(&mut self, segments: &[&str]) -> Option<&mut Item> {
+        let (first, rest) = segments.split_first()?;
+        let first = self.document.as_table_mut().get_mut(first)?;
+        rest.iter()
+            .try_fold(first, |item, segment| match item {
+                Item::Table(table) => table.get_mut(segment),
+                Item::Value(Value::InlineTable(table)) => TableLike::get_mut(table, segment),
+                _ => None,
+            })
+            .filter(|item| !item.is_none())
+    }
+
+    fn item_mut(&mut self, key: &str) -> Result<&mut Item> {
+        let segments = segments(key)?;
+        let (first, rest) = segments.split_first().unwrap();
+        let mut item = &mut self.document[first];
+        for (i, segment) in rest.iter().enumerate() {
+            if item.is_none() {
+                *item = table();
+                if let Some(table) = item.as_table_mut() {
+                    table.set_implicit(true);
+                }
+            } else if !item.is_table_like() {
+                bail!("`{}` is not a table", segments[..=i].join("."));
+            }
+            item = &mut item[segment];
+        }
+        Ok(item)
+    }
+}
+
+fn segments(key: &str) -> Result<Vec<&str>> {
+    let segments = key.split('.').collect::<Vec<_>>();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        bail!("Invalid key `{}`", key);
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const DRONE_TOML: &str = "\
+# Memory layout
+[memory.flash]
+size = \"1M\" # Whole bank
+origin = 0x08000000
+
+[memory.ram]
+size   =   \"64K\"
+origin = 0x20000000
+
+[heap.main]
+size = \"16K\"
+pools = [{ block = \"4\", capacity = 4096 }]
+
+# SWO at the core clock
+[log.swo]
+reset-freq = 8000000
+baud-rate = 115200
+serial-endpoint = \"/dev/ttyUSB0\"
+
+[linker]
+platform = \"arm\"
+";
+
+    fn open(drone_toml: &str) -> (TempDir, ConfigFile) {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(CONFIG_NAME), drone_toml).unwrap();
+        let file = ConfigFile::open(dir.path(), false, None).unwrap();
+        (dir, file)
+    }
+
+    #[test]
+    fn set_keeps_comments_and_whitespace() {
+        let (_dir, mut file) = open(DRONE_TOML);
+        file.set("memory.flash.size", "2M").unwrap();
+        file.set("memory.ram.origin", "0x20001000").unwrap();
+        let expected = DRONE_TOML
+            .replace("size = \"1M\" # Whole bank", "size = \"2M\" # Whole bank")
+            .replace("origin = 0x20000000", "origin = 0x20001000");
+        assert_eq!(file.render().unwrap(), expected);
+    }
+
+    #[test]
+    fn set_creates_dotted_tables() {
+        let (_dir, mut file) = open(DRONE_TOML);
+        file.set("log.dso.baud-rate", "115200").unwrap();
+        file.set("log.dso.serial-endpoint", "/dev/ttyACM0").unwrap();
+        file.set("probe.gdb-client-command", "gdb-multiarch").unwrap();
+        let expected = DRONE_TOML.replace(
+            "[linker]\n",
+            "[log.dso]\nbaud-rate = 115200\nserial-endpoint = \"/dev/ttyACM0\"\n\n[linker]\n",
+        ) + "\n[probe]\ngdb-client-command = \"gdb-multiarch\"\n";
+        assert_eq!(file.render().unwrap(), expected);
+    }
+
+    #[test]
+    fn unset() {
+        let (_dir, mut file) = open(DRONE_TOML);
+        assert!(file.unset("log.swo.serial-endpoint").unwrap());
+        assert!(!file.unset("log.swo.serial-endpoint").unwrap());
+        assert!(!file.unset("probe.bmp.device").unwrap());
+        assert!(!file.unset("log.dso").unwrap());
+        assert!(file.get("probe").is_none());
+        assert!(file.get("log.dso").is_none());
+        assert_eq!(
+            file.render().unwrap(),
+            DRONE_TOML.replace("serial-endpoint = \"/dev/ttyUSB0\"\n", "")
+        );
+        assert!(file.unset("..").is_err());
+    }
+
+    #[test]
+    fn take_and_replace() {
+        let (_dir, mut file) = open(DRONE_TOML);
+        let swo = file.take("log.swo").unwrap();
+        assert_eq!(
+            swo.as_table().unwrap().get("reset-freq").unwrap().as_integer(),
+            Some(8_000_000)
+        );
+        let start = DRONE_TOML.find("# SWO").unwrap();
+        let end = DRONE_TOML.find("[linker]").unwrap();
+        let without_swo = [&DRONE_TOML[..start], &DRONE_TOML[end..]].concat();
+        assert_eq!(file.render().unwrap(), without_swo);
+        file.replace("log.swo", swo).unwrap();
+        assert_eq!(file.render().unwrap(), DRONE_TOML);
+    }
+
+    #[test]
+    fn render_rejects_invalid() {
+        let (dir, mut file) = open(DRONE_TOML);
+        file.set("heap.main.size", "32K").unwrap();
+        let err = file.render().unwrap_err().to_string();
+        assert!(err.ends_with("`heap.main.pools` adds up to 16384, but `heap.main.size = 32768`"));
+        assert!(file.save().is_err());
+        assert_eq!(fs::read_to_string(dir.path().join(CONFIG_NAME)).unwrap(), DRONE_TOML);
+    }
+}
@@ -0,0 +1,25 @@
+//! Heap traces multiplexed in a single log capture.
+
+use crate::{
+    log::{self, OutputMap, OutputStream, Recording},
+    probe::Log,
+};
+use anyhow::Result;
+use std::{collections::BTreeMap, io::Read, sync::mpsc};
+
+/// Decodes a raw `capture` of the `log` protocol, or its recording, and
+/// returns the payloads of each of `ports`.
+pub fn demux(capture: impl Read, log: Log, ports: &[u32]) -> Result<BTreeMap<u32, Vec<u8>>> {
+    let mut outputs = OutputMap::new(&[])?;
+    let mut receivers = Vec::with_capacity(ports.len());
+    for &port in ports {
+        let (sender, receiver) = mpsc::channel();
+        outputs.push(vec![port], OutputStream::Channel(sender));
+        receivers.push((port, receiver));
+    }
+    log::feed(Recording::detect(capture)?, outputs, log::parser(log))?;
+    Ok(receivers
+        .into_iter()
+        .map(|(port, receiver)| (port, receiver.try_iter().flatten().collect()))
+        .collect())
+}
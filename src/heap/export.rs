@@ -0,0 +1,174 @@
+//! Machine-readable heap trace output.
+
+use super::{trace::Packet, Load, TraceMap};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Output format of the heap trace data.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable text.
+    Text,
+    /// JSON.
+    Json,
+    /// Comma-separated values.
+    Csv,
+}
+
+/// Summary of a heap trace.
+#[derive(Serialize)]
+pub struct Summary {
+    /// Heap size.
+    pub size: u64,
+    /// Maximum simultaneous heap load.
+    pub peak: u64,
+    /// Index of the packet which reached the peak.
+    pub peak_packet: usize,
+    /// Number of processed packets.
+    pub packets: usize,
+    /// Sum of the maximum loads of every block size.
+    pub max_sum: u64,
+    /// Per-size statistics.
+    pub blocks: Vec<BlockSummary>,
+}
+
+/// Statistics of a single block size.
+#[derive(Serialize)]
+pub struct BlockSummary {
+    /// Block size.
+    pub size: u32,
+    /// Currently allocated blocks.
+    pub cur: u32,
+    /// Maximum allocated blocks.
+    pub max: u32,
+    /// Allocated blocks at the moment of the simultaneous peak.
+    pub peak: u32,
+    /// Total allocated blocks.
+    pub total: u32,
+}
+
+/// Writer of the decoded trace packets.
+pub struct PacketWriter<W: Write> {
+    w: W,
+    format: Format,
+    index: usize,
+}
+
+#[derive(Serialize)]
+struct PacketRecord<'a> {
+    index: usize,
+    #[serde(flatten)]
+    packet: &'a Packet,
+}
+
+impl Summary {
+    /// Summarizes the processed `trace`.
+    pub fn new(trace: &TraceMap, load: &Load, size: u64) -> Self {
+        let blocks = trace
+            .iter()
+            .map(|(&size, entry)| BlockSummary {
+                size,
+                cur: entry.cur,
+                max: entry.max,
+                peak: entry.peak,
+                total: entry.total,
+            })
+            .collect::<Vec<_>>();
+        let max_sum = blocks.iter().map(|block| u64::from(block.size) * u64::from(block.max)).sum();
+        Self {
+            size,
+            peak: load.peak,
+            peak_packet: load.peak_packet,
+            packets: load.packets,
+            max_sum,
+            blocks,
+        }
+    }
+
+    /// Writes the summary as a JSON object.
+    pub fn write_json(&self, w: &mut impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *w, self)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    /// Writes the per-size statistics as CSV with a header row.
+    pub fn write_csv(&self, w: &mut impl Write) -> Result<()> {
+        writeln!(w, "size,cur,max,peak,total")?;
+        for block in &self.blocks {
+            writeln!(
+                w,
+                "{},{},{},{},{}",
+                block.size, block.cur, block.max, block.peak, block.total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> PacketWriter<W> {
+    /// Creates a new writer, and writes the CSV header if needed.
+    ///
+    /// JSON packets are written as JSON Lines, one object per line.
+    pub fn new(mut w: W, format: Format) -> Result<Self> {
+        if format == Format::Csv {
+            writeln!(w, "index,kind,size,new_size,addr,pc")?;
+        }
+        Ok(Self { w, format, index: 0 })
+    }
+
+    /// Writes the next `packet`.
+    pub fn write(&mut self, packet: &Packet) -> Result<()> {
+        let index = self.index;
+        let (kind, size, new_size, addr, pc) = fields(packet);
+        match self.format {
+            Format::Text => {
+                write!(self.w, "{:>8} {:<7} {}", index, kind, size)?;
+                if let Some(new_size) = new_size {
+                    write!(self.w, " -> {}", new_size)?;
+                }
+                if let Some(addr) = addr {
+                    write!(self.w, " at 0x{:08X}", addr)?;
+                }
+                if let Some(pc) = pc {
+                    write!(self.w, " from 0x{:08X}", pc)?;
+                }
+                writeln!(self.w)?;
+            }
+            Format::Json => {
+                serde_json::to_writer(&mut self.w, &PacketRecord { index, packet })?;
+                writeln!(self.w)?;
+            }
+            Format::Csv => {
+                let optional = |value: Option<u32>| value.map(|value| value.to_string());
+                writeln!(
+                    self.w,
+                    "{},{},{},{},{},{}",
+                    index,
+                    kind,
+                    size,
+                    optional(new_size).unwrap_or_default(),
+                    optional(addr).unwrap_or_default(),
+                    optional(pc).unwrap_or_default()
+                )?;
+            }
+        }
+        self.index += 1;
+        Ok(())
+    }
+}
+
+/// Returns the kind, the size, the new size, the address, and the caller PC of
+/// `packet`.
+fn fields(packet: &Packet) -> (&'static str, u32, Option<u32>, Option<u32>, Option<u32>) {
+    match *packet {
+        Packet::Alloc { size, addr, pc } => ("alloc", size, None, addr, pc),
+        Packet::Dealloc { size, addr } => ("dealloc", size, None, addr, None),
+        Packet::Grow { old_size, new_size, addr } => ("grow", old_size, Some(new_size), addr, None),
+        Packet::Shrink { old_size, new_size, addr } => {
+            ("shrink", old_size, Some(new_size), addr, None)
+        }
+    }
+}
@@ -1,14 +1,63 @@
 //! Heap layout generation.
 
-use super::TraceMap;
-use anyhow::Result;
-use drone_config::format_size;
-use std::io::Write;
+use super::{TraceEntry, TraceMap};
+use anyhow::{bail, Result};
+use drone_config::{format_size, WORD_SIZE};
+use std::{fmt::Write as _, io::Write};
 
-const WORD_SIZE: u32 = 4;
+/// Source of the per-size block counts for [`optimize`].
+#[derive(Clone, Copy, Debug)]
+pub enum Occupancy {
+    /// The maximum load of each block size. It never underestimates, but the
+    /// maxima are generally reached at different moments.
+    Max,
+    /// The load of each block size at the moment of the simultaneous peak.
+    Peak,
+}
+
+impl Occupancy {
+    fn count(self, entry: &TraceEntry) -> u32 {
+        match self {
+            Self::Max => entry.max,
+            Self::Peak => entry.peak,
+        }
+    }
+}
+
+/// Upper bound of the number of pools for the automatic selection.
+pub const AUTO_MAX_POOLS: usize = 32;
+
+/// User constraints for [`optimize`].
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    /// Block sizes which must be present in the layout.
+    pub pinned: Vec<u64>,
+    /// Minimum block size.
+    pub min_block: Option<u64>,
+    /// Maximum block size.
+    pub max_block: Option<u64>,
+    /// Minimum spare capacity of each pool, in percents of its load.
+    pub spare: f64,
+}
+
+/// Optimized layout.
+pub struct Optimized {
+    /// Block sizes and capacities of the pools.
+    pub layout: Vec<(u64, u32)>,
+    /// Fragmentation of the layout.
+    pub frag: u64,
+    /// Fragmentation for each feasible number of pools.
+    pub curve: Vec<(u32, u64)>,
+}
+
+struct Partition {
+    frag: Vec<Vec<u128>>,
+    split: Vec<Vec<usize>>,
+    counts: Vec<u128>,
+}
 
 /// Generates a new empty layout for the given `size` and `pools`.
-pub fn empty(size: u32, pools: u32) -> Vec<(u32, u32)> {
+pub fn empty(size: u64, pools: u32) -> Vec<(u64, u32)> {
     let pool_min = WORD_SIZE;
     let pool_max = size / 20;
     let mut layout = Vec::with_capacity(pools as usize);
@@ -16,14 +65,14 @@ pub fn empty(size: u32, pools: u32) -> Vec<(u32, u32)> {
     let mut prev_block = 0;
     for (i, ratio) in ratios(pools).into_iter().enumerate() {
         let mut block = pool_min
-            + ((i as f64 / f64::from(pools - 1)).powf(2.75) * f64::from(pool_max - pool_min))
-                .round() as u32;
+            + ((i as f64 / f64::from(pools - 1)).powf(2.75) * (pool_max - pool_min) as f64).round()
+                as u64;
         block = align(block);
         if block <= prev_block {
             block = prev_block + WORD_SIZE;
         }
-        let capacity = add_capacity(block, size - used, ratio, f64::from(size));
-        used += block * capacity;
+        let capacity = add_capacity(block, size - used, ratio, size as f64);
+        used += block * u64::from(capacity);
         prev_block = block;
         layout.push((block, capacity));
     }
@@ -32,88 +81,226 @@ pub fn empty(size: u32, pools: u32) -> Vec<(u32, u32)> {
 }
 
 /// Creates an optimized layout based on heaptrace.
-pub fn optimize(trace: &TraceMap, size: u32, mut pools: u32) -> Result<(Vec<(u32, u32)>, u32)> {
-    let mut input = Vec::<(u32, u32)>::with_capacity(trace.len());
+///
+/// If `pools` is `None`, the number of pools is picked at the knee of the
+/// fragmentation curve.
+pub fn optimize(
+    trace: &TraceMap,
+    size: u64,
+    pools: Option<u32>,
+    occupancy: Occupancy,
+    constraints: &Constraints,
+) -> Result<Optimized> {
+    let Constraints { pinned, min_block, max_block, spare } = constraints;
+    let min_block = align(min_block.unwrap_or(0));
+    let mut input = Vec::<(u64, u32)>::with_capacity(trace.len());
     let mut used = 0;
     let mut prev_size = 0;
     for (size, entry) in trace {
-        let size = align(*size);
+        let size = align(u64::from(*size)).max(min_block);
+        let count = occupancy.count(entry);
         if size == prev_size {
-            input.iter_mut().last().unwrap().1 += entry.max;
+            input.iter_mut().last().unwrap().1 += count;
         } else {
-            input.push((size, entry.max));
+            input.push((size, count));
             prev_size = size;
         }
-        used += size * entry.max;
+        used += size * u64::from(count);
     }
-    if (input.len() as u32) < pools {
-        pools = input.len() as u32;
+    if used > size {
+        bail!("Peak heap load {} exceeds the heap size {}", used, size);
+    }
+    for &block in pinned {
+        if block % WORD_SIZE != 0 {
+            bail!("Pinned block size {} is not a multiple of {}", block, WORD_SIZE);
+        }
+        if block < min_block {
+            bail!("Pinned block size {} is below the minimum block size {}", block, min_block);
+        }
+        if let Err(i) = input.binary_search_by_key(&block, |(size, _)| *size) {
+            input.insert(i, (block, 0));
+        }
+    }
+    if let (Some(max_block), Some((largest, _))) = (max_block, input.last()) {
+        if largest > max_block {
+            bail!("Block size {} exceeds the maximum block size {}", largest, max_block);
+        }
+    }
+    let pins = input.iter().map(|(size, _)| pinned.contains(size)).collect::<Vec<_>>();
+    let min_pools = pins.iter().filter(|pin| **pin).count()
+        + usize::from(!pins.last().copied().unwrap_or(true));
+    let max_pools = match pools {
+        Some(0) => bail!("At least one pool is required"),
+        Some(pools) => pools as usize,
+        None => AUTO_MAX_POOLS,
+    }
+    .min(input.len());
+    if max_pools < min_pools {
+        bail!("{} pinned block sizes need at least {} pools", pinned.len(), min_pools);
+    }
+    let partition = Partition::new(&input, &pins, max_pools);
+    let curve = (min_pools..=max_pools)
+        .filter_map(|pools| Some((pools as u32, partition.frag(pools)?)))
+        .collect::<Vec<_>>();
+    let pools = if pools.is_some() { max_pools } else { knee(&curve) as usize };
+    let frag = partition.frag(pools).unwrap();
+    let mut output = partition.layout(&input, pools);
+    let mut reserved = 0;
+    for (block, capacity) in &mut output {
+        let spare = (f64::from(*capacity) * spare / 100.0).ceil() as u32;
+        *capacity = (*capacity + spare).max(u32::from(pinned.contains(block)));
+        reserved += *block * u64::from(*capacity);
+    }
+    if reserved > size {
+        bail!(
+            "Pools with {}% spare capacity need {} bytes, but the heap size is {}",
+            spare,
+            reserved,
+            size
+        );
     }
-    let mut output = Vec::with_capacity(pools as usize);
-    output.resize_with(output.capacity(), Default::default);
-    let mut frag = 0;
-    shrink(&input, &mut output, &mut frag, size - used);
     extend(&mut output, size);
-    Ok((output, frag))
+    Ok(Optimized { layout: output, frag, curve })
+}
+
+/// Computes the fragmentation of `layout` for the trace, i.e. the bytes
+/// wasted when each block size takes the smallest fitting pool.
+///
+/// Block sizes which don't fit any pool are not taken into account.
+pub fn fragmentation(trace: &TraceMap, layout: &[(u64, u32)], occupancy: Occupancy) -> u64 {
+    let mut frag = 0;
+    for (size, entry) in trace {
+        let size = align(u64::from(*size));
+        if let Some(block) = layout.iter().map(|(block, _)| *block).filter(|b| *b >= size).min() {
+            frag += (block - size) * u64::from(occupancy.count(entry));
+        }
+    }
+    frag
 }
 
 /// Renders `[heap.<key>]` section for `Drone.toml`.
-pub fn render(w: &mut impl Write, key: &str, layout: &[(u32, u32)]) -> Result<()> {
-    let size = layout.iter().map(|(size, count)| size * count).sum::<u32>();
+pub fn render(w: &mut impl Write, key: &str, layout: &[(u64, u32)]) -> Result<()> {
     writeln!(w, "[heap.{}]", key)?;
-    writeln!(w, "size = \"{}\"", format_size(size))?;
-    writeln!(w, "pools = [")?;
+    writeln!(w, "size = \"{}\"", format_size(size(layout)))?;
+    writeln!(w, "pools = {}", render_pools(layout))?;
+    Ok(())
+}
+
+/// Renders the `pools` array of `[heap.<key>]` section for `Drone.toml`.
+pub fn render_pools(layout: &[(u64, u32)]) -> String {
+    let mut pools = String::from("[\n");
     for (block, capacity) in layout {
         if *capacity == 0 {
             continue;
         }
-        writeln!(w, "    {{ block = \"{}\", capacity = {} }},", format_size(*block), capacity)?;
+        let _ = writeln!(
+            pools,
+            "    {{ block = \"{}\", capacity = {} }},",
+            format_size(*block),
+            capacity
+        );
     }
-    writeln!(w, "]")?;
-    Ok(())
+    pools.push(']');
+    pools
 }
 
-fn shrink(input: &[(u32, u32)], output: &mut [(u32, u32)], frag: &mut u32, cutoff: u32) {
-    if output.len() == 1 {
-        let (max_block, mut total) = input[input.len() - 1];
-        for (block, capacity) in &input[..input.len() - 1] {
-            *frag += (max_block - block) * capacity;
-            total += capacity;
-        }
-        output[0] = (max_block, total);
-    } else {
-        let (mut opt_i, mut opt_frag) = (0, cutoff);
-        for i in 0..input.len() - output.len() {
-            let mut cur_frag = *frag;
-            let (max_block, _) = input[i];
-            for (block, capacity) in input.iter().take(i) {
-                cur_frag += (max_block - block) * capacity;
-            }
-            if cur_frag < opt_frag {
-                shrink(&input[i + 1..], &mut output[1..], &mut cur_frag, opt_frag);
-                if cur_frag <= opt_frag {
-                    opt_i = i;
-                    opt_frag = cur_frag;
+/// Returns the total size of the pools in `layout`.
+pub fn size(layout: &[(u64, u32)]) -> u64 {
+    layout.iter().map(|(block, capacity)| block * u64::from(*capacity)).sum()
+}
+
+impl Partition {
+    /// Partitions the sorted `input` into up to `max_pools` contiguous groups
+    /// with the minimal total fragmentation. A `pins` entry marks a size which
+    /// must be the largest in its group.
+    ///
+    /// Each group is served by a pool with the block size of its largest
+    /// member. The optimum is found by dynamic programming over the prefixes
+    /// of `input` in `O(k * n^2)` time, where `k` is `max_pools`.
+    fn new(input: &[(u64, u32)], pins: &[bool], max_pools: usize) -> Self {
+        let (n, k) = (input.len(), max_pools);
+        let mut counts = vec![0_u128; n + 1];
+        let mut bytes = vec![0_u128; n + 1];
+        let mut pinned = vec![0; n + 1];
+        for (i, (size, count)) in input.iter().enumerate() {
+            counts[i + 1] = counts[i] + u128::from(*count);
+            bytes[i + 1] = bytes[i] + u128::from(*size) * u128::from(*count);
+            pinned[i + 1] = pinned[i] + usize::from(pins[i]);
+        }
+        // Fragmentation of serving `input[i..j]` with a single pool, unless a
+        // pinned size is not the last one.
+        let cost = |i: usize, j: usize| {
+            (pinned[j - 1] == pinned[i]).then(|| {
+                u128::from(input[j - 1].0) * (counts[j] - counts[i]) - (bytes[j] - bytes[i])
+            })
+        };
+        // `frag[p][j]` is the minimal fragmentation of `input[..j]` split into
+        // `p` groups, and `split[p][j]` is the start of the last group.
+        let mut frag = vec![vec![u128::MAX; n + 1]; k + 1];
+        let mut split = vec![vec![0; n + 1]; k + 1];
+        frag[0][0] = 0;
+        for p in 1..=k {
+            for j in p..=n {
+                for i in p - 1..j {
+                    if frag[p - 1][i] == u128::MAX {
+                        continue;
+                    }
+                    if let Some(cost) = cost(i, j) {
+                        let cur = frag[p - 1][i] + cost;
+                        if cur < frag[p][j] {
+                            frag[p][j] = cur;
+                            split[p][j] = i;
+                        }
+                    }
                 }
             }
         }
-        let (max_block, mut total) = input[opt_i];
-        for (block, capacity) in input.iter().take(opt_i) {
-            *frag += (max_block - block) * capacity;
-            total += capacity;
+        Self { frag, split, counts }
+    }
+
+    /// Returns the minimal fragmentation with `pools` pools, if feasible.
+    fn frag(&self, pools: usize) -> Option<u64> {
+        let frag = *self.frag[pools].last().unwrap();
+        (frag != u128::MAX).then_some(frag as u64)
+    }
+
+    /// Returns the optimal layout with `pools` pools and without spare
+    /// capacity.
+    fn layout(&self, input: &[(u64, u32)], pools: usize) -> Vec<(u64, u32)> {
+        let mut output = vec![(0, 0); pools];
+        let mut j = input.len();
+        for p in (1..=pools).rev() {
+            let i = self.split[p][j];
+            output[p - 1] = (input[j - 1].0, (self.counts[j] - self.counts[i]) as u32);
+            j = i;
         }
-        output[0] = (max_block, total);
-        shrink(&input[opt_i + 1..], &mut output[1..], frag, opt_frag);
+        output
+    }
+}
+
+/// Picks the number of pools at the knee of the decreasing fragmentation
+/// `curve`, i.e. the point farthest from the chord between its ends.
+fn knee(curve: &[(u32, u64)]) -> u32 {
+    let (first, last) = (curve[0], curve[curve.len() - 1]);
+    if first.1 == last.1 {
+        return first.0;
     }
+    let x = |pools: u32| f64::from(pools - first.0) / f64::from((last.0 - first.0).max(1));
+    let y = |frag: u64| (frag - last.1) as f64 / (first.1 - last.1) as f64;
+    curve
+        .iter()
+        .map(|&(pools, frag)| (pools, 1.0 - x(pools) - y(frag)))
+        .fold((first.0, f64::MIN), |best, cur| if cur.1 > best.1 { cur } else { best })
+        .0
 }
 
-fn extend(output: &mut [(u32, u32)], size: u32) {
-    let mut used = output.iter().map(|(block, capacity)| block * capacity).sum::<u32>();
+fn extend(output: &mut [(u64, u32)], size: u64) {
+    let mut used = output.iter().map(|(block, capacity)| block * u64::from(*capacity)).sum::<u64>();
     let count = output.len() as u32;
-    let free = f64::from(size - used);
+    let free = (size - used) as f64;
     for ((block, capacity), ratio) in output.iter_mut().zip(ratios(count)) {
         let add = add_capacity(*block, size - used, ratio, free);
-        used += add * *block;
+        used += u64::from(add) * *block;
         *capacity += add;
     }
     add_up_to_size(output, &mut used, size);
@@ -135,25 +322,258 @@ fn ratios(n: u32) -> Vec<f64> {
     ratios
 }
 
-fn add_capacity(block: u32, free: u32, ratio: f64, total: f64) -> u32 {
-    let mut capacity = (total / f64::from(block) * ratio).round() as u32;
-    if block * capacity > free {
-        capacity -= (f64::from(block * capacity - free) / f64::from(block)).ceil() as u32;
+fn add_capacity(block: u64, free: u64, ratio: f64, total: f64) -> u32 {
+    let mut capacity = (total / block as f64 * ratio).round() as u32;
+    if block * u64::from(capacity) > free {
+        capacity -= ((block * u64::from(capacity) - free) as f64 / block as f64).ceil() as u32;
     }
     capacity
 }
 
-fn add_up_to_size(layout: &mut [(u32, u32)], used: &mut u32, size: u32) {
+fn add_up_to_size(layout: &mut [(u64, u32)], used: &mut u64, size: u64) {
     for (block, capacity) in layout.iter_mut().rev() {
-        let add = (size - *used) / *block;
+        let add = ((size - *used) / *block).min(u64::from(u32::MAX - *capacity));
         *used += add * *block;
-        *capacity += add;
+        *capacity += add as u32;
     }
 }
 
-fn align(mut value: u32) -> u32 {
+fn align(mut value: u64) -> u64 {
     if value % WORD_SIZE > 0 {
         value += WORD_SIZE - value % WORD_SIZE;
     }
     value
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use test::Bencher;
+
+    /// Heap size large enough for any generated trace.
+    const SIZE: u64 = 1 << 20;
+
+    /// Xorshift generator, to keep the random traces reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+
+        /// Generates a trace of up to `max_sizes` unaligned block sizes.
+        fn trace(&mut self, max_sizes: u64) -> TraceMap {
+            (0..=self.next(max_sizes))
+                .map(|_| {
+                    let size = 1 + self.next(40) as u32;
+                    (size, TraceEntry { max: self.next(6) as u32, ..TraceEntry::default() })
+                })
+                .collect()
+        }
+    }
+
+    /// Finds the minimal fragmentation among the layouts of `pools` distinct
+    /// block sizes, trying every combination of the word-aligned sizes in
+    /// `blocks`. Returns `None` if no combination serves every size in `trace`
+    /// and passes `allowed`.
+    fn brute_force(
+        trace: &TraceMap,
+        blocks: (u64, u64),
+        pools: usize,
+        allowed: &dyn Fn(&[u64]) -> bool,
+    ) -> Option<u64> {
+        fn combinations(
+            candidates: &[u64],
+            pools: usize,
+            layout: &mut Vec<u64>,
+            f: &mut dyn FnMut(&[u64]),
+        ) {
+            if layout.len() == pools {
+                f(layout);
+                return;
+            }
+            for (i, &block) in candidates.iter().enumerate() {
+                layout.push(block);
+                combinations(&candidates[i + 1..], pools, layout, f);
+                layout.pop();
+            }
+        }
+        let candidates = (blocks.0..=blocks.1).step_by(WORD_SIZE as usize).collect::<Vec<_>>();
+        let pools = pools.min(candidates.len());
+        let mut best = None;
+        combinations(&candidates, pools, &mut Vec::new(), &mut |blocks| {
+            if !allowed(blocks) {
+                return;
+            }
+            let mut frag = 0;
+            for (&size, entry) in trace {
+                let size = align(u64::from(size));
+                match blocks.iter().find(|&&block| block >= size) {
+                    Some(block) => frag += (block - size) * u64::from(entry.max),
+                    None => return,
+                }
+            }
+            best = Some(best.map_or(frag, |best: u64| best.min(frag)));
+        });
+        best
+    }
+
+    fn largest(trace: &TraceMap) -> u64 {
+        trace.keys().map(|&size| align(u64::from(size))).max().unwrap()
+    }
+
+    fn pool_load(trace: &TraceMap, layout: &[(u64, u32)], block: u64) -> u32 {
+        trace
+            .iter()
+            .filter(|(&size, _)| {
+                layout.iter().map(|(block, _)| *block).find(|&b| b >= align(u64::from(size)))
+                    == Some(block)
+            })
+            .map(|(_, entry)| entry.max)
+            .sum()
+    }
+
+    /// Checks that the pools take the whole heap, up to the smallest block.
+    fn assert_filled(layout: &[(u64, u32)]) {
+        assert!(size(layout) <= SIZE && SIZE - size(layout) < layout[0].0, "{:?}", layout);
+    }
+
+    #[test]
+    fn optimal() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..300 {
+            let trace = rng.trace(4);
+            for pools in 1..=3 {
+                let constraints = Constraints::default();
+                let optimized =
+                    optimize(&trace, SIZE, Some(pools), Occupancy::Max, &constraints).unwrap();
+                let expected =
+                    brute_force(&trace, (WORD_SIZE, largest(&trace)), pools as usize, &|_| true);
+                assert_eq!(Some(optimized.frag), expected, "{:?}", optimized.layout);
+                assert_eq!(
+                    fragmentation(&trace, &optimized.layout, Occupancy::Max),
+                    optimized.frag
+                );
+                assert_filled(&optimized.layout);
+            }
+        }
+    }
+
+    #[test]
+    fn pinned() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..300 {
+            let trace = rng.trace(4);
+            let pinned =
+                (0..rng.next(3)).map(|_| WORD_SIZE * (1 + rng.next(12))).collect::<Vec<_>>();
+            let max = largest(&trace).max(pinned.iter().copied().max().unwrap_or(0));
+            let constraints = Constraints { pinned: pinned.clone(), ..Constraints::default() };
+            for pools in 1..=3 {
+                let optimized = optimize(&trace, SIZE, Some(pools), Occupancy::Max, &constraints);
+                let expected = brute_force(&trace, (WORD_SIZE, max), pools as usize, &|blocks| {
+                    pinned.iter().all(|pin| blocks.contains(pin))
+                });
+                let optimized = match (optimized, expected) {
+                    (Ok(optimized), Some(expected)) => {
+                        assert_eq!(optimized.frag, expected, "{:?}", optimized.layout);
+                        optimized
+                    }
+                    (Err(_), None) => continue,
+                    (optimized, expected) => {
+                        panic!(
+                            "{:?} with {:?}: {:?}",
+                            pinned,
+                            expected,
+                            optimized.map(|o| o.layout)
+                        )
+                    }
+                };
+                for pin in &pinned {
+                    assert!(optimized.layout.iter().any(|(block, _)| block == pin));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn min_max_block() {
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+        for _ in 0..300 {
+            let trace = rng.trace(4);
+            let min_block = WORD_SIZE * (1 + rng.next(6));
+            let constraints = Constraints { min_block: Some(min_block), ..Constraints::default() };
+            let raised = trace
+                .iter()
+                .map(|(&size, entry)| {
+                    let size = u64::from(size).max(min_block) as u32;
+                    (size, TraceEntry { max: entry.max, ..TraceEntry::default() })
+                })
+                .fold(TraceMap::new(), |mut raised, (size, entry)| {
+                    raised.entry(size).or_default().max += entry.max;
+                    raised
+                });
+            for pools in 1..=3 {
+                let optimized =
+                    optimize(&trace, SIZE, Some(pools), Occupancy::Max, &constraints).unwrap();
+                let expected =
+                    brute_force(&raised, (min_block, largest(&raised)), pools as usize, &|_| true);
+                assert_eq!(Some(optimized.frag), expected, "{:?}", optimized.layout);
+                assert!(optimized.layout.iter().all(|(block, _)| *block >= min_block));
+            }
+            let max_block = largest(&trace);
+            let constraints = Constraints { max_block: Some(max_block), ..Constraints::default() };
+            let optimized = optimize(&trace, SIZE, None, Occupancy::Max, &constraints).unwrap();
+            assert!(optimized.layout.iter().all(|(block, _)| *block <= max_block));
+            let constraints =
+                Constraints { max_block: Some(max_block - WORD_SIZE), ..Constraints::default() };
+            assert!(optimize(&trace, SIZE, None, Occupancy::Max, &constraints).is_err());
+        }
+    }
+
+    #[test]
+    fn spare() {
+        let mut rng = Rng(0x94d0_49bb_1331_11eb);
+        for _ in 0..300 {
+            let trace = rng.trace(4);
+            let spare = rng.next(200) as f64;
+            let constraints = Constraints { spare, ..Constraints::default() };
+            let optimized = optimize(&trace, SIZE, None, Occupancy::Max, &constraints).unwrap();
+            for &(block, capacity) in &optimized.layout {
+                let load = pool_load(&trace, &optimized.layout, block);
+                let required = load + (f64::from(load) * spare / 100.0).ceil() as u32;
+                assert!(
+                    capacity >= required,
+                    "{} < {} in {:?}",
+                    capacity,
+                    required,
+                    optimized.layout
+                );
+            }
+            assert_filled(&optimized.layout);
+        }
+        let trace =
+            std::iter::once((32, TraceEntry { max: 10, ..TraceEntry::default() })).collect();
+        let constraints = Constraints { spare: 100.0, ..Constraints::default() };
+        assert!(optimize(&trace, 640, None, Occupancy::Max, &constraints).is_ok());
+        assert!(optimize(&trace, 636, None, Occupancy::Max, &constraints).is_err());
+    }
+
+    #[bench]
+    fn bench_partition(b: &mut Bencher) {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut size = 0;
+        let input = (0..24)
+            .map(|_| {
+                size += WORD_SIZE * (1 + rng.next(8));
+                (size, rng.next(6) as u32)
+            })
+            .collect::<Vec<_>>();
+        let pins = vec![false; input.len()];
+        b.iter(|| Partition::new(&input, &pins, 8).frag(8));
+    }
+}
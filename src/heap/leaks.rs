@@ -0,0 +1,146 @@
+//! Leak detection and block lifetimes.
+
+use super::trace::Packet;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Block tracked by its address.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Block {
+    /// Block address.
+    pub addr: u32,
+    /// Block size.
+    pub size: u32,
+    /// Caller PC of the allocation.
+    pub pc: Option<u32>,
+    /// Index of the allocation packet.
+    pub packet: usize,
+}
+
+/// Lifetime distribution of a block size, in packets.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Lifetimes {
+    /// Block size.
+    pub size: u32,
+    /// Number of freed blocks.
+    pub freed: usize,
+    /// Number of blocks outstanding at the end of the trace.
+    pub outstanding: usize,
+    /// Shortest lifetime.
+    pub min: usize,
+    /// Median lifetime.
+    pub median: usize,
+    /// 90th percentile lifetime.
+    pub p90: usize,
+    /// Longest lifetime.
+    pub max: usize,
+}
+
+/// Tracker of individual blocks, based on the extended trace packets.
+#[derive(Default)]
+pub struct Tracker {
+    live: BTreeMap<u32, Block>,
+    lifetimes: BTreeMap<u32, Vec<usize>>,
+    /// Number of packets without a block address.
+    pub anonymous: usize,
+    /// Number of packets referring to an unknown block.
+    pub unmatched: usize,
+    packets: usize,
+}
+
+impl Tracker {
+    /// Applies a single trace packet.
+    pub fn apply(&mut self, packet: &Packet) {
+        match *packet {
+            Packet::Alloc { size, addr: Some(addr), pc } => {
+                let block = Block { addr, size, pc, packet: self.packets };
+                if self.live.insert(addr, block).is_some() {
+                    self.unmatched += 1;
+                }
+            }
+            Packet::Dealloc { addr: Some(addr), .. } => {
+                if let Some(block) = self.live.remove(&addr) {
+                    let lifetime = self.packets - block.packet;
+                    self.lifetimes.entry(block.size).or_default().push(lifetime);
+                } else {
+                    self.unmatched += 1;
+                }
+            }
+            Packet::Grow { new_size, addr: Some(addr), .. }
+            | Packet::Shrink { new_size, addr: Some(addr), .. } => {
+                if let Some(block) = self.live.get_mut(&addr) {
+                    block.size = new_size;
+                } else {
+                    self.unmatched += 1;
+                }
+            }
+            Packet::Alloc { addr: None, .. }
+            | Packet::Dealloc { addr: None, .. }
+            | Packet::Grow { addr: None, .. }
+            | Packet::Shrink { addr: None, .. } => {
+                self.anonymous += 1;
+            }
+        }
+        self.packets += 1;
+    }
+
+    /// Returns `true` if there were packets, but none of them carried a block
+    /// address.
+    pub fn is_anonymous(&self) -> bool {
+        self.packets > 0 && self.anonymous == self.packets
+    }
+
+    /// Returns the blocks outstanding at the end of the trace, in the order of
+    /// allocation.
+    pub fn leaks(&self) -> Vec<Block> {
+        let mut leaks = self.live.values().copied().collect::<Vec<_>>();
+        leaks.sort_by_key(|block| block.packet);
+        leaks
+    }
+
+    /// Returns the lifetime distribution of each block size.
+    pub fn lifetimes(&self) -> Vec<Lifetimes> {
+        let mut outstanding = BTreeMap::<u32, usize>::new();
+        for block in self.live.values() {
+            *outstanding.entry(block.size).or_default() += 1;
+        }
+        let mut sizes =
+            self.lifetimes.keys().chain(outstanding.keys()).copied().collect::<Vec<_>>();
+        sizes.sort_unstable();
+        sizes.dedup();
+        sizes
+            .into_iter()
+            .map(|size| {
+                let mut lifetimes = self.lifetimes.get(&size).cloned().unwrap_or_default();
+                lifetimes.sort_unstable();
+                let percentile = |p: usize| lifetimes.get(lifetimes.len() * p / 100).copied();
+                Lifetimes {
+                    size,
+                    freed: lifetimes.len(),
+                    outstanding: outstanding.get(&size).copied().unwrap_or(0),
+                    min: lifetimes.first().copied().unwrap_or(0),
+                    median: percentile(50).unwrap_or(0),
+                    p90: percentile(90).unwrap_or(0),
+                    max: lifetimes.last().copied().unwrap_or(0),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymous() {
+        let mut tracker = Tracker::default();
+        assert!(!tracker.is_anonymous());
+        assert!(tracker.leaks().is_empty());
+        tracker.apply(&Packet::Alloc { size: 16, addr: None, pc: None });
+        assert!(tracker.is_anonymous());
+        tracker.apply(&Packet::Alloc { size: 16, addr: Some(0x2000_0000), pc: None });
+        assert!(!tracker.is_anonymous());
+        assert_eq!(tracker.leaks().len(), 1);
+    }
+}
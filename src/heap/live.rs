@@ -0,0 +1,135 @@
+//! Live heap trace analysis.
+
+use super::{simulate::Allocator, trace::Parser, Load, TraceMap};
+use anyhow::{Error, Result};
+use drone_config::HeapPool;
+use std::{
+    io,
+    io::Read,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    thread,
+};
+
+/// State of a live heap trace.
+#[derive(Default)]
+pub struct LiveTrace {
+    /// Processed trace map.
+    pub trace: TraceMap,
+    /// Model of the allocator with the configured pools.
+    pub allocator: Allocator,
+    /// Simultaneous heap load.
+    pub load: Load,
+    /// Number of corrupted bytes skipped.
+    pub skipped: u64,
+    /// The error which stopped the trace processing.
+    pub error: Option<Error>,
+}
+
+/// [`Read`] adapter for trace chunks received from a log output.
+struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+/// Spawns a thread which processes the trace chunks from `receiver`.
+///
+/// The allocations are replayed against `pools` with [`Allocator`]. The
+/// stream is parsed and replayed leniently, as the log transport may drop bytes
+/// and the trace may start after some blocks were allocated.
+pub fn spawn(
+    receiver: Receiver<Vec<u8>>,
+    max_size: u64,
+    pools: &[HeapPool],
+) -> Arc<Mutex<LiveTrace>> {
+    let allocator = Allocator::new(pools);
+    let state = Arc::new(Mutex::new(LiveTrace { allocator, ..LiveTrace::default() }));
+    let reader = ChannelReader { receiver, chunk: Vec::new(), position: 0 };
+    let thread_state = Arc::clone(&state);
+    thread::spawn(move || {
+        if let Err(err) = process(reader, &thread_state, max_size) {
+            thread_state.lock().unwrap().error = Some(err);
+        }
+    });
+    state
+}
+
+fn process(reader: ChannelReader, state: &Mutex<LiveTrace>, max_size: u64) -> Result<()> {
+    let mut parser = Parser::new(reader, true)?;
+    while let Some(packet) = parser.next() {
+        let packet = packet?;
+        let mut state = state.lock().unwrap();
+        let LiveTrace { trace, load, allocator, skipped, .. } = &mut *state;
+        *skipped = parser.skipped();
+        if let Some(packet) = super::replay(trace, load, packet, max_size, true)? {
+            allocator.apply(&packet);
+        }
+    }
+    Ok(())
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.chunk.len() - self.position);
+        buf[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::trace::KEY;
+    use std::sync::mpsc;
+
+    fn alloc(size: u32) -> Vec<[u8; 4]> {
+        let [a, b, c, d] = size.to_be_bytes();
+        vec![[0xA1, a, b, c], [0xA2, 0, 0, d]]
+    }
+
+    fn dealloc(size: u32) -> Vec<[u8; 4]> {
+        let [a, b, c, d] = size.to_be_bytes();
+        vec![[0xD1, a, b, c], [0xD2, 0, 0, d]]
+    }
+
+    fn grow(old_size: u32, new_size: u32) -> Vec<[u8; 4]> {
+        let old = old_size.to_be_bytes();
+        let new = new_size.to_be_bytes();
+        vec![[0xB1, old[0], old[1], old[2]], [0xB2, old[3], new[0], new[1]], [
+            0xB3, 0, new[2], new[3],
+        ]]
+    }
+
+    fn run(packets: &[Vec<[u8; 4]>]) -> LiveTrace {
+        let (sender, receiver) = mpsc::channel();
+        for frame in packets.iter().flatten() {
+            sender.send((u32::from_be_bytes(*frame) ^ KEY).to_le_bytes().to_vec()).unwrap();
+        }
+        drop(sender);
+        let state = Mutex::new(LiveTrace::default());
+        let reader = ChannelReader { receiver, chunk: Vec::new(), position: 0 };
+        process(reader, &state, 1024).unwrap();
+        state.into_inner().unwrap()
+    }
+
+    #[test]
+    fn skipped_packets() {
+        let state = run(&[dealloc(16), alloc(16), grow(32, 48), dealloc(16), dealloc(16)]);
+        assert_eq!(state.load.skipped_packets, 3);
+        assert_eq!(state.load.packets, 3);
+        assert_eq!(state.load.cur, 48);
+        assert_eq!(state.trace[&16].total, 1);
+        assert_eq!(state.trace[&16].cur, 0);
+        assert_eq!(state.trace[&48].cur, 1);
+    }
+}
@@ -1,11 +1,18 @@
 //! Heap layout management.
 
+pub mod demux;
+pub mod export;
 pub mod layout;
+pub mod leaks;
+pub mod live;
+pub mod report;
+pub mod simulate;
+pub mod symbols;
 pub mod trace;
 
 use self::trace::{Packet, Parser};
-use anyhow::{bail, Result};
-use std::{collections::BTreeMap, fs::File};
+use anyhow::{anyhow, bail, Result};
+use std::{collections::BTreeMap, fs::File, io, io::Read, path::Path};
 
 /// Processed trace map.
 pub type TraceMap = BTreeMap<u32, TraceEntry>;
@@ -19,33 +26,134 @@ pub struct TraceEntry {
     pub max: u32,
     /// Total allocated bytes.
     pub total: u32,
+    /// Allocated blocks at the moment of the simultaneous peak.
+    pub peak: u32,
 }
 
-/// Reads the trace file.
-pub fn read_trace(trace: &mut TraceMap, trace_file: File, max_size: u32) -> Result<()> {
-    let parser = Parser::new(trace_file)?;
+/// Simultaneous heap load over the trace.
+///
+/// Unlike the sum of [`TraceEntry::max`] values, which are reached at
+/// different moments, this is the real peak of the outstanding bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Load {
+    /// Currently outstanding bytes.
+    pub cur: u64,
+    /// Maximum outstanding bytes.
+    pub peak: u64,
+    /// Index of the packet which reached the peak.
+    pub peak_packet: usize,
+    /// Number of processed packets.
+    pub packets: usize,
+    /// Number of packets skipped by a lenient replay, see [`replay`].
+    pub skipped_packets: u64,
+}
+
+/// Reads the trace packets from `parser`.
+///
+/// If `lenient`, the packets which don't match the trace are skipped as in
+/// [`replay`].
+pub fn read_trace(
+    trace: &mut TraceMap,
+    parser: &mut Parser,
+    max_size: u64,
+    lenient: bool,
+) -> Result<Load> {
+    let mut load = Load::default();
     for packet in parser {
-        let packet = packet?;
-        match packet {
-            Packet::Alloc { size } => {
-                alloc(trace, size, max_size)?;
-            }
-            Packet::Dealloc { size } => {
-                dealloc(trace, size)?;
-            }
-            Packet::Grow { old_size, new_size } | Packet::Shrink { old_size, new_size } => {
-                dealloc(trace, old_size)?;
-                alloc(trace, new_size, max_size)?;
-            }
+        replay(trace, &mut load, packet?, max_size, lenient)?;
+    }
+    Ok(load)
+}
+
+/// Opens the trace file at `path`, or the standard input if `path` is `-`.
+pub fn open_trace(path: &Path) -> Result<Box<dyn Read>> {
+    if path == Path::new("-") {
+        Ok(Box::new(io::stdin()))
+    } else {
+        let file = File::open(path)
+            .map_err(|err| anyhow!("Couldn't open `{}`: {}", path.display(), err))?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Applies a single trace packet.
+pub fn apply(trace: &mut TraceMap, load: &mut Load, packet: &Packet, max_size: u64) -> Result<()> {
+    match *packet {
+        Packet::Alloc { size, .. } => {
+            alloc(trace, load, size, max_size)?;
+        }
+        Packet::Dealloc { size, .. } => {
+            dealloc(trace, load, size)?;
+        }
+        Packet::Grow { old_size, new_size, .. } | Packet::Shrink { old_size, new_size, .. } => {
+            dealloc(trace, load, old_size)?;
+            alloc(trace, load, new_size, max_size)?;
         }
     }
+    if load.cur > load.peak {
+        load.peak = load.cur;
+        load.peak_packet = load.packets;
+        for entry in trace.values_mut() {
+            entry.peak = entry.cur;
+        }
+    }
+    load.packets += 1;
     Ok(())
 }
 
-fn alloc(trace: &mut TraceMap, size: u32, max_size: u32) -> Result<()> {
-    if size > max_size {
+/// Applies a single trace packet, skipping it if it doesn't match the trace and
+/// `lenient` is set.
+///
+/// When a lenient parser skips a corrupted region, the packets of the region
+/// are lost, so the following packets may free blocks which were never
+/// allocated. The same happens when the trace starts after some blocks were
+/// allocated. Such a deallocation is skipped, and such a reallocation becomes
+/// an allocation of the new size. An allocation larger than `max_size` is
+/// skipped as well. The changed packets are counted in
+/// [`Load::skipped_packets`].
+///
+/// Returns the packet as it was applied, or `None` if it was skipped.
+pub fn replay(
+    trace: &mut TraceMap,
+    load: &mut Load,
+    packet: Packet,
+    max_size: u64,
+    lenient: bool,
+) -> Result<Option<Packet>> {
+    let packet = if lenient { skip_orphan(trace, load, packet, max_size) } else { Some(packet) };
+    if let Some(packet) = &packet {
+        apply(trace, load, packet, max_size)?;
+    }
+    Ok(packet)
+}
+
+fn skip_orphan(trace: &TraceMap, load: &mut Load, packet: Packet, max_size: u64) -> Option<Packet> {
+    let (old_size, new_size) = match packet {
+        Packet::Alloc { size, .. } => (None, Some(size)),
+        Packet::Dealloc { size, .. } => (Some(size), None),
+        Packet::Grow { old_size, new_size, .. } | Packet::Shrink { old_size, new_size, .. } => {
+            (Some(old_size), Some(new_size))
+        }
+    };
+    let known = old_size.map_or(true, |size| trace.get(&size).map_or(false, |entry| entry.cur > 0));
+    let fits = new_size.map_or(true, |size| u64::from(size) <= max_size);
+    if known && fits {
+        return Some(packet);
+    }
+    load.skipped_packets += 1;
+    match packet {
+        Packet::Grow { new_size, addr, .. } | Packet::Shrink { new_size, addr, .. } if fits => {
+            Some(Packet::Alloc { size: new_size, addr, pc: None })
+        }
+        _ => None,
+    }
+}
+
+fn alloc(trace: &mut TraceMap, load: &mut Load, size: u32, max_size: u64) -> Result<()> {
+    if u64::from(size) > max_size {
         bail!("Trace file is corrupted");
     }
+    load.cur += u64::from(size);
     let entry = trace.entry(size).or_default();
     entry.cur += 1;
     entry.total += 1;
@@ -55,11 +163,82 @@ fn alloc(trace: &mut TraceMap, size: u32, max_size: u32) -> Result<()> {
     Ok(())
 }
 
-fn dealloc(trace: &mut TraceMap, size: u32) -> Result<()> {
+fn dealloc(trace: &mut TraceMap, load: &mut Load, size: u32) -> Result<()> {
     let entry = trace.entry(size).or_default();
     if entry.cur == 0 {
         bail!("Trace file is corrupted");
     }
     entry.cur -= 1;
+    load.cur -= u64::from(size);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::trace::KEY;
+    use std::io::Cursor;
+
+    fn encode(frames: &[[u8; 4]]) -> Vec<u8> {
+        frames
+            .iter()
+            .flat_map(|frame| (u32::from_be_bytes(*frame) ^ KEY).to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn alloc(size: u8) -> Vec<u8> {
+        encode(&[[0xA1, 0, 0, 0], [0xA2, 0, 0, size]])
+    }
+
+    fn dealloc(size: u8) -> Vec<u8> {
+        encode(&[[0xD1, 0, 0, 0], [0xD2, 0, 0, size]])
+    }
+
+    /// A trace which lost the second frame of the allocation of the 32-byte
+    /// block, so the lenient parser resyncs on its deallocation.
+    fn resynced() -> Vec<u8> {
+        let mut dropped = alloc(32);
+        dropped.truncate(5);
+        [alloc(16), dropped, dealloc(32), dealloc(16), alloc(8)].concat()
+    }
+
+    fn read(trace: Vec<u8>, lenient: bool) -> Result<(TraceMap, Load, usize)> {
+        let mut map = TraceMap::new();
+        let mut parser = Parser::new(Cursor::new(trace), true)?;
+        let load = read_trace(&mut map, &mut parser, 1024, lenient)?;
+        Ok((map, load, parser.corruptions().len()))
+    }
+
+    #[test]
+    fn orphan_dealloc() {
+        let (trace, load, corruptions) = read(resynced(), true).unwrap();
+        assert_eq!(corruptions, 1);
+        assert_eq!(load.skipped_packets, 1);
+        assert_eq!(load.packets, 3);
+        assert_eq!(load.cur, 8);
+        assert_eq!(load.peak, 16);
+        assert!(!trace.contains_key(&32));
+        assert_eq!((trace[&16].cur, trace[&16].total), (0, 1));
+        assert_eq!((trace[&8].cur, trace[&8].total), (1, 1));
+    }
+
+    #[test]
+    fn orphan_dealloc_strict() {
+        let err = read(resynced(), false).map(drop).unwrap_err();
+        assert_eq!(err.to_string(), "Trace file is corrupted");
+    }
+
+    #[test]
+    fn orphan_grow() {
+        let mut trace = TraceMap::new();
+        let mut load = Load::default();
+        let grow = Packet::Grow { old_size: 16, new_size: 48, addr: Some(0x2000_0000) };
+        let packet = replay(&mut trace, &mut load, grow, 1024, true).unwrap();
+        assert!(matches!(packet, Some(Packet::Alloc { size: 48, addr: Some(0x2000_0000), .. })));
+        let alloc = Packet::Alloc { size: 2048, addr: None, pc: None };
+        assert!(replay(&mut trace, &mut load, alloc, 1024, true).unwrap().is_none());
+        assert!(replay(&mut trace, &mut load, alloc, 1024, false).is_err());
+        assert_eq!(load.skipped_packets, 2);
+        assert_eq!(load.cur, 48);
+    }
+}
@@ -0,0 +1,398 @@
+//! HTML heap report.
+
+use super::{
+    layout,
+    layout::{Constraints, Occupancy, Optimized},
+    simulate::{Allocator, PoolLoad},
+    trace::Packet,
+    Load, TraceMap,
+};
+use anyhow::Result;
+use drone_config::{format_size, HeapBlock};
+use std::{convert::TryFrom, fmt::Write};
+
+/// Maximum number of occupancy samples in the timeline.
+const MAX_SAMPLES: usize = 500;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 300.0;
+const BAR_HEIGHT: f64 = 22.0;
+const MARGIN: f64 = 60.0;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; }
+td, th { padding: 0.2em 0.8em; text-align: right; }
+.columns { display: flex; gap: 2em; flex-wrap: wrap; }
+svg text { font-size: 11px; }
+.error { color: #b00; }";
+
+/// Heap occupancy sampled over the trace.
+pub struct Timeline {
+    step: usize,
+    packets: usize,
+    samples: Vec<(usize, Vec<(u32, u32)>)>,
+}
+
+/// Heap report of a single trace.
+pub struct Report {
+    heap: String,
+    size: u64,
+    trace: TraceMap,
+    load: Load,
+    timeline: Timeline,
+    configured: Allocator,
+    configured_frag: u64,
+    optimized: Result<(Optimized, Allocator)>,
+}
+
+impl Timeline {
+    /// Creates an empty timeline for a trace of `packets` packets.
+    pub fn new(packets: usize) -> Self {
+        Self { step: (packets / MAX_SAMPLES).max(1), packets, samples: Vec::new() }
+    }
+
+    /// Records the state of `trace` after the packet at `index`.
+    pub fn record(&mut self, index: usize, trace: &TraceMap) {
+        if index % self.step == 0 || index + 1 == self.packets {
+            let sample = trace.iter().map(|(&size, entry)| (size, entry.cur)).collect();
+            self.samples.push((index, sample));
+        }
+    }
+}
+
+impl Report {
+    /// Replays `packets` against the `[heap.<heap>]` configuration and the
+    /// optimized layout with the same number of pools.
+    ///
+    /// If `lenient`, the packets which don't match the trace are skipped as in
+    /// [`super::replay`].
+    pub fn new(
+        heap: &str,
+        block: &HeapBlock,
+        size: u64,
+        packets: &[Packet],
+        lenient: bool,
+    ) -> Result<Self> {
+        let mut trace = TraceMap::new();
+        let mut load = Load::default();
+        let mut timeline = Timeline::new(packets.len());
+        let mut configured = Allocator::new(&block.pools);
+        let mut replayed = Vec::with_capacity(packets.len());
+        for (index, &packet) in packets.iter().enumerate() {
+            if let Some(packet) = super::replay(&mut trace, &mut load, packet, size, lenient)? {
+                configured.apply(&packet);
+                replayed.push(packet);
+            }
+            timeline.record(index, &trace);
+        }
+        let layout = block.pools.iter().map(|pool| (pool.block, pool.capacity)).collect::<Vec<_>>();
+        let configured_frag = layout::fragmentation(&trace, &layout, Occupancy::Max);
+        let pools = u32::try_from(layout.len()).ok().filter(|&pools| pools > 0);
+        let optimized =
+            layout::optimize(&trace, size, pools, Occupancy::Max, &Constraints::default()).map(
+                |optimized| {
+                    let mut allocator = Allocator::with_layout(&optimized.layout);
+                    for packet in &replayed {
+                        allocator.apply(packet);
+                    }
+                    (optimized, allocator)
+                },
+            );
+        Ok(Self {
+            heap: heap.to_string(),
+            size,
+            trace,
+            load,
+            timeline,
+            configured,
+            configured_frag,
+            optimized,
+        })
+    }
+
+    /// Renders a self-contained HTML document.
+    pub fn render(&self) -> Result<String> {
+        let mut out = String::new();
+        let title = format!("Heap report: [heap.{}]", escape(&self.heap));
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(out, "<html>")?;
+        writeln!(out, "<head>")?;
+        writeln!(out, "<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>{}</title>", title)?;
+        writeln!(out, "<style>\n{}\n</style>", STYLE)?;
+        writeln!(out, "</head>")?;
+        writeln!(out, "<body>")?;
+        writeln!(out, "<h1>{}</h1>", title)?;
+        self.summary(&mut out)?;
+        writeln!(out, "<h2>Occupancy</h2>")?;
+        self.occupancy(&mut out)?;
+        writeln!(out, "<h2>Allocation sizes</h2>")?;
+        self.histogram(&mut out)?;
+        writeln!(out, "<h2>Pools</h2>")?;
+        writeln!(out, "<div class=\"columns\">")?;
+        writeln!(out, "<section>")?;
+        writeln!(out, "<h3>Configured</h3>")?;
+        self.frag(&mut out, self.configured_frag)?;
+        pools(&mut out, &self.configured.pools, self.configured.failure_count)?;
+        writeln!(out, "</section>")?;
+        writeln!(out, "<section>")?;
+        writeln!(out, "<h3>Optimized</h3>")?;
+        match &self.optimized {
+            Ok((optimized, allocator)) => {
+                self.frag(&mut out, optimized.frag)?;
+                pools(&mut out, &allocator.pools, allocator.failure_count)?;
+            }
+            Err(err) => {
+                writeln!(out, "<p class=\"error\">{}</p>", escape(&err.to_string()))?;
+            }
+        }
+        writeln!(out, "</section>")?;
+        writeln!(out, "</div>")?;
+        writeln!(out, "</body>")?;
+        writeln!(out, "</html>")?;
+        Ok(out)
+    }
+
+    fn summary(&self, out: &mut String) -> Result<()> {
+        writeln!(out, "<table>")?;
+        writeln!(out, "<tr><th>Heap size</th><td>{}</td></tr>", self.size)?;
+        writeln!(out, "<tr><th>Packets</th><td>{}</td></tr>", self.load.packets)?;
+        if self.load.skipped_packets > 0 {
+            writeln!(
+                out,
+                "<tr><th>Skipped packets</th><td class=\"error\">{}</td></tr>",
+                self.load.skipped_packets
+            )?;
+        }
+        writeln!(
+            out,
+            "<tr><th>Peak heap load</th><td>{} / {:.2}% at packet {}</td></tr>",
+            self.load.peak,
+            self.load.peak as f64 / self.size as f64 * 100.0,
+            self.load.peak_packet
+        )?;
+        writeln!(out, "</table>")?;
+        Ok(())
+    }
+
+    fn frag(&self, out: &mut String, frag: u64) -> Result<()> {
+        writeln!(
+            out,
+            "<p>Fragmentation: {} / {:.2}%</p>",
+            frag,
+            frag as f64 / self.size as f64 * 100.0
+        )?;
+        Ok(())
+    }
+
+    /// Stacked area chart of the outstanding bytes of each block size.
+    fn occupancy(&self, out: &mut String) -> Result<()> {
+        let sizes = self.trace.keys().copied().collect::<Vec<_>>();
+        let samples = &self.timeline.samples;
+        let max = self.load.peak.max(self.size).max(1) as f64;
+        let last = self.timeline.packets.saturating_sub(1).max(1) as f64;
+        let x = |index: usize| MARGIN + index as f64 / last * WIDTH;
+        let y = |bytes: u64| HEIGHT - bytes as f64 / max * HEIGHT + 10.0;
+        let mut lower = vec![0; samples.len()];
+        svg_open(out, MARGIN * 2.0 + WIDTH, HEIGHT + 40.0)?;
+        for (i, &size) in sizes.iter().enumerate() {
+            let upper = samples
+                .iter()
+                .zip(&lower)
+                .map(|((_, sample), lower)| {
+                    let cur = sample
+                        .binary_search_by_key(&size, |&(size, _)| size)
+                        .map_or(0, |j| sample[j].1);
+                    lower + u64::from(size) * u64::from(cur)
+                })
+                .collect::<Vec<_>>();
+            write!(out, "<polygon fill=\"{}\" points=\"", hue(i, sizes.len()))?;
+            for ((index, _), bytes) in samples.iter().zip(&upper) {
+                write!(out, "{:.1},{:.1} ", x(*index), y(*bytes))?;
+            }
+            for ((index, _), bytes) in samples.iter().zip(&lower).rev() {
+                write!(out, "{:.1},{:.1} ", x(*index), y(*bytes))?;
+            }
+            writeln!(out, "\"><title>{}</title></polygon>", format_size(u64::from(size)))?;
+            lower = upper;
+        }
+        let heap = y(self.size);
+        writeln!(
+            out,
+            "<line x1=\"{0}\" y1=\"{1:.1}\" x2=\"{2}\" y2=\"{1:.1}\" stroke=\"#b00\" \
+             stroke-dasharray=\"4\"/>",
+            MARGIN,
+            heap,
+            MARGIN + WIDTH
+        )?;
+        writeln!(
+            out,
+            "<text x=\"{}\" y=\"{:.1}\" fill=\"#b00\">heap size</text>",
+            MARGIN,
+            heap - 4.0
+        )?;
+        axes(out, MARGIN, y(0), WIDTH, &max.to_string(), "0", &last.to_string())?;
+        writeln!(out, "</svg>")?;
+        legend(out, &sizes)?;
+        Ok(())
+    }
+
+    /// Bar chart of the total number of allocations of each size.
+    fn histogram(&self, out: &mut String) -> Result<()> {
+        let count = self.trace.len().max(1);
+        let max = self.trace.values().map(|entry| entry.total).max().unwrap_or(0).max(1);
+        let width = WIDTH / count as f64;
+        svg_open(out, MARGIN * 2.0 + WIDTH, HEIGHT + 40.0)?;
+        for (i, (size, entry)) in self.trace.iter().enumerate() {
+            let height = f64::from(entry.total) / f64::from(max) * HEIGHT;
+            writeln!(
+                out,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" \
+                 fill=\"{}\"><title>{}: {} allocations</title></rect>",
+                MARGIN + i as f64 * width,
+                HEIGHT + 10.0 - height,
+                (width - 1.0).max(1.0),
+                height,
+                hue(i, count),
+                format_size(u64::from(*size)),
+                entry.total
+            )?;
+        }
+        let first = self.trace.keys().next().map_or(0, |&size| size);
+        let last = self.trace.keys().last().map_or(0, |&size| size);
+        axes(
+            out,
+            MARGIN,
+            HEIGHT + 10.0,
+            WIDTH,
+            &max.to_string(),
+            &first.to_string(),
+            &last.to_string(),
+        )?;
+        writeln!(out, "</svg>")?;
+        Ok(())
+    }
+}
+
+/// Horizontal bars of the pool capacities overlaid with the maximum loads.
+fn pools(out: &mut String, pools: &[PoolLoad], failures: usize) -> Result<()> {
+    const BAR: f64 = 300.0;
+    if pools.is_empty() {
+        writeln!(out, "<p>No pools.</p>")?;
+        return Ok(());
+    }
+    svg_open(out, BAR + 200.0, BAR_HEIGHT * pools.len() as f64 + 10.0)?;
+    for (i, pool) in pools.iter().enumerate() {
+        let top = 5.0 + i as f64 * BAR_HEIGHT;
+        let used = if pool.capacity == 0 {
+            0.0
+        } else {
+            f64::from(pool.max.min(pool.capacity)) / f64::from(pool.capacity) * BAR
+        };
+        let fill = if pool.max >= pool.capacity { "#d9534f" } else { "#5b9bd5" };
+        writeln!(
+            out,
+            "<text x=\"70\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            top + 14.0,
+            format_size(pool.block)
+        )?;
+        writeln!(
+            out,
+            "<rect x=\"80\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+            top,
+            used,
+            BAR_HEIGHT - 4.0,
+            fill
+        )?;
+        writeln!(
+            out,
+            "<rect x=\"80\" y=\"{:.1}\" width=\"{}\" height=\"{:.1}\" fill=\"none\" \
+             stroke=\"#444\"/>",
+            top,
+            BAR,
+            BAR_HEIGHT - 4.0
+        )?;
+        writeln!(
+            out,
+            "<text x=\"{}\" y=\"{:.1}\">{} / {}</text>",
+            BAR + 90.0,
+            top + 14.0,
+            pool.max,
+            pool.capacity
+        )?;
+    }
+    writeln!(out, "</svg>")?;
+    if failures > 0 {
+        writeln!(out, "<p class=\"error\">Failed allocations: {}</p>", failures)?;
+    }
+    Ok(())
+}
+
+fn svg_open(out: &mut String, width: f64, height: f64) -> Result<()> {
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} \
+         {1}\">",
+        width, height
+    )?;
+    Ok(())
+}
+
+fn axes(
+    out: &mut String,
+    left: f64,
+    bottom: f64,
+    width: f64,
+    top_label: &str,
+    left_label: &str,
+    right_label: &str,
+) -> Result<()> {
+    let top = bottom - HEIGHT;
+    writeln!(
+        out,
+        "<path d=\"M{0} {1:.1} V{2:.1} H{3:.1}\" fill=\"none\" stroke=\"#444\"/>",
+        left,
+        top,
+        bottom,
+        left + width
+    )?;
+    writeln!(
+        out,
+        "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+        left - 4.0,
+        top + 10.0,
+        top_label
+    )?;
+    writeln!(out, "<text x=\"{}\" y=\"{:.1}\">{}</text>", left, bottom + 14.0, left_label)?;
+    writeln!(
+        out,
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+        left + width,
+        bottom + 14.0,
+        right_label
+    )?;
+    Ok(())
+}
+
+fn legend(out: &mut String, sizes: &[u32]) -> Result<()> {
+    writeln!(out, "<p>")?;
+    for (i, size) in sizes.iter().enumerate() {
+        writeln!(
+            out,
+            "<span style=\"color: {}\">&#9632;</span> {}",
+            hue(i, sizes.len()),
+            format_size(u64::from(*size))
+        )?;
+    }
+    writeln!(out, "</p>")?;
+    Ok(())
+}
+
+fn hue(index: usize, count: usize) -> String {
+    format!("hsl({:.0}, 60%, 55%)", index as f64 / count.max(1) as f64 * 300.0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,268 @@
+//! Pool allocator simulation.
+
+use super::trace::{Packet, Parser};
+use anyhow::Result;
+use drone_config::HeapPool;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Maximum number of failed allocations to record.
+pub const MAX_FAILURES: usize = 10;
+
+/// Load of a configured pool.
+#[derive(Clone, Debug, Serialize)]
+pub struct PoolLoad {
+    /// Block size of the pool.
+    pub block: u64,
+    /// Number of blocks in the pool.
+    pub capacity: u32,
+    /// Currently allocated blocks.
+    pub cur: u32,
+    /// Maximum allocated blocks.
+    pub max: u32,
+    /// Currently wasted bytes, i.e. unused tails of the allocated blocks.
+    pub waste: u64,
+    /// Wasted bytes at the moment of the maximum load.
+    pub max_waste: u64,
+}
+
+/// Allocation which doesn't fit into the pools.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Failure {
+    /// Index of the packet.
+    pub packet: usize,
+    /// Requested size.
+    pub size: u32,
+}
+
+/// Model of the Drone pool allocator.
+///
+/// An allocation takes a block from the smallest pool which fits it, and
+/// falls back to larger pools when that pool is exhausted. Deallocations are
+/// matched to their blocks by address when the trace carries addresses.
+/// Otherwise allocations of the same size are assumed to be freed in the
+/// reverse order.
+#[derive(Clone, Debug, Default)]
+pub struct Allocator {
+    /// Pools sorted by the block size.
+    pub pools: Vec<PoolLoad>,
+    /// The first [`MAX_FAILURES`] allocations which would fail.
+    pub failures: Vec<Failure>,
+    /// Total number of allocations which would fail.
+    pub failure_count: usize,
+    live: BTreeMap<u32, Vec<Option<usize>>>,
+    addressed: BTreeMap<u32, (u32, Option<usize>)>,
+    packets: usize,
+}
+
+/// Replays the trace packets from `parser` against `pools`.
+pub fn simulate(parser: &mut Parser, pools: &[HeapPool]) -> Result<Allocator> {
+    let mut allocator = Allocator::new(pools);
+    for packet in parser {
+        allocator.apply(&packet?);
+    }
+    Ok(allocator)
+}
+
+impl PoolLoad {
+    /// Returns the share of the capacity which was never used, in percents.
+    pub fn headroom(&self) -> f64 {
+        if self.capacity == 0 {
+            0.0
+        } else {
+            f64::from(self.capacity.saturating_sub(self.max)) / f64::from(self.capacity) * 100.0
+        }
+    }
+}
+
+impl Allocator {
+    /// Creates a new allocator model with empty `pools`.
+    pub fn new(pools: &[HeapPool]) -> Self {
+        Self::with_layout(&pools.iter().map(|pool| (pool.block, pool.capacity)).collect::<Vec<_>>())
+    }
+
+    /// Creates a new allocator with pools of `(block size, capacity)` pairs.
+    pub fn with_layout(layout: &[(u64, u32)]) -> Self {
+        let mut pools = layout
+            .iter()
+            .map(|&(block, capacity)| PoolLoad {
+                block,
+                capacity,
+                cur: 0,
+                max: 0,
+                waste: 0,
+                max_waste: 0,
+            })
+            .collect::<Vec<_>>();
+        pools.sort_by_key(|pool| pool.block);
+        Self { pools, ..Self::default() }
+    }
+
+    /// Applies a single trace packet.
+    pub fn apply(&mut self, packet: &Packet) {
+        match *packet {
+            Packet::Alloc { size, addr, .. } => self.alloc(size, addr),
+            Packet::Dealloc { size, addr } => {
+                if let Some((size, index)) = self.take(size, addr) {
+                    self.release(size, index);
+                }
+            }
+            Packet::Grow { old_size, new_size, addr } => match self.take(old_size, addr) {
+                Some((old_size, Some(index))) if self.pools[index].block >= u64::from(new_size) => {
+                    self.resize(old_size, new_size, addr, index);
+                }
+                Some((old_size, index)) => {
+                    self.alloc(new_size, addr);
+                    self.release(old_size, index);
+                }
+                None => self.alloc(new_size, addr),
+            },
+            Packet::Shrink { old_size, new_size, addr } => match self.take(old_size, addr) {
+                Some((old_size, Some(index))) => self.resize(old_size, new_size, addr, index),
+                Some((_, None)) => self.put(new_size, addr, None),
+                None => {}
+            },
+        }
+        self.packets += 1;
+    }
+
+    fn alloc(&mut self, size: u32, addr: Option<u32>) {
+        let index = self
+            .pools
+            .iter()
+            .position(|pool| pool.block >= u64::from(size) && pool.cur < pool.capacity);
+        if let Some(index) = index {
+            let pool = &mut self.pools[index];
+            pool.cur += 1;
+            pool.waste += pool.block - u64::from(size);
+            if pool.cur > pool.max {
+                pool.max = pool.cur;
+                pool.max_waste = pool.waste;
+            }
+        } else {
+            if self.failures.len() < MAX_FAILURES {
+                self.failures.push(Failure { packet: self.packets, size });
+            }
+            self.failure_count += 1;
+        }
+        self.put(size, addr, index);
+    }
+
+    fn release(&mut self, size: u32, index: Option<usize>) {
+        if let Some(index) = index {
+            let pool = &mut self.pools[index];
+            pool.cur -= 1;
+            pool.waste -= pool.block - u64::from(size);
+        }
+    }
+
+    fn resize(&mut self, old_size: u32, new_size: u32, addr: Option<u32>, index: usize) {
+        let pool = &mut self.pools[index];
+        pool.waste = pool.waste + u64::from(old_size) - u64::from(new_size);
+        self.put(new_size, addr, Some(index));
+    }
+
+    /// Records a live block of `size` taken from the pool at `index`.
+    fn put(&mut self, size: u32, addr: Option<u32>, index: Option<usize>) {
+        match addr {
+            Some(addr) => {
+                self.addressed.insert(addr, (size, index));
+            }
+            None => self.live.entry(size).or_default().push(index),
+        }
+    }
+
+    /// Removes the live block at `addr`, or the last allocated block of
+    /// `size` if the address is unknown, and returns its recorded size and
+    /// pool index.
+    fn take(&mut self, size: u32, addr: Option<u32>) -> Option<(u32, Option<usize>)> {
+        addr.and_then(|addr| self.addressed.remove(&addr))
+            .or_else(|| Some((size, self.live.get_mut(&size)?.pop()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alloc(size: u32, addr: Option<u32>) -> Packet {
+        Packet::Alloc { size, addr, pc: None }
+    }
+
+    fn dealloc(size: u32, addr: Option<u32>) -> Packet {
+        Packet::Dealloc { size, addr }
+    }
+
+    fn run(layout: &[(u64, u32)], packets: &[Packet]) -> Allocator {
+        let mut allocator = Allocator::with_layout(layout);
+        for packet in packets {
+            allocator.apply(packet);
+        }
+        allocator
+    }
+
+    fn load(allocator: &Allocator) -> Vec<(u64, u32, u64)> {
+        allocator.pools.iter().map(|pool| (pool.block, pool.cur, pool.waste)).collect()
+    }
+
+    #[test]
+    fn smallest_fitting_pool() {
+        let allocator = run(&[(32, 2), (4, 2), (8, 2)], &[
+            alloc(5, None),
+            alloc(4, None),
+            alloc(1, None),
+            alloc(32, None),
+        ]);
+        assert_eq!(load(&allocator), [(4, 2, 3), (8, 1, 3), (32, 1, 0)]);
+        assert_eq!(allocator.failure_count, 0);
+    }
+
+    #[test]
+    fn fallback_to_larger_pool() {
+        let allocator = run(&[(4, 1), (16, 1)], &[alloc(4, None), alloc(2, None)]);
+        assert_eq!(load(&allocator), [(4, 1, 0), (16, 1, 14)]);
+        assert_eq!(allocator.pools[1].max, 1);
+        assert!(allocator.failures.is_empty());
+    }
+
+    #[test]
+    fn failure_when_full() {
+        let mut packets = vec![alloc(4, None), alloc(4, None), alloc(8, None), dealloc(4, None)];
+        packets.extend((0..20).map(|_| alloc(3, None)));
+        let allocator = run(&[(4, 1), (8, 1)], &packets);
+        assert_eq!(allocator.failure_count, 20);
+        assert_eq!(allocator.failures.len(), MAX_FAILURES);
+        assert_eq!(allocator.failures[0].packet, 2);
+        assert_eq!(allocator.failures[0].size, 8);
+        assert_eq!(allocator.failures[1].packet, 5);
+        assert_eq!(allocator.failures[1].size, 3);
+        assert_eq!(load(&allocator), [(4, 1, 0), (8, 1, 5)]);
+        assert_eq!(allocator.pools[0].max, 1);
+    }
+
+    #[test]
+    fn dealloc_by_address() {
+        let packets = [alloc(8, Some(0x100)), alloc(8, Some(0x200)), dealloc(8, Some(0x100))];
+        let allocator = run(&[(8, 1), (16, 1)], &packets);
+        assert_eq!(load(&allocator), [(8, 0, 0), (16, 1, 8)]);
+        let anonymous = [alloc(8, None), alloc(8, None), dealloc(8, None)];
+        let allocator = run(&[(8, 1), (16, 1)], &anonymous);
+        assert_eq!(load(&allocator), [(8, 1, 0), (16, 0, 0)]);
+    }
+
+    #[test]
+    fn resize_by_address() {
+        let allocator = run(&[(8, 2), (16, 1)], &[
+            alloc(4, Some(0x100)),
+            alloc(6, Some(0x200)),
+            Packet::Grow { old_size: 4, new_size: 8, addr: Some(0x100) },
+            Packet::Shrink { old_size: 6, new_size: 2, addr: Some(0x200) },
+            Packet::Grow { old_size: 8, new_size: 12, addr: Some(0x100) },
+            dealloc(12, Some(0x100)),
+        ]);
+        assert_eq!(load(&allocator), [(8, 1, 6), (16, 0, 0)]);
+        assert_eq!(allocator.pools[1].max, 1);
+        assert_eq!(allocator.pools[0].max, 2);
+        assert_eq!(allocator.failure_count, 0);
+    }
+}
@@ -0,0 +1,41 @@
+//! Symbolization of code addresses.
+
+/// Function symbols of a firmware ELF.
+#[derive(Default)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+}
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+impl Symbols {
+    /// Parses the output of `llvm-nm --print-size --demangle --defined-only`.
+    pub fn parse(nm: &str) -> Self {
+        let mut symbols = nm
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(4, ' ');
+                let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let size = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let kind = fields.next()?;
+                let name = fields.next()?.to_string();
+                matches!(kind, "t" | "T").then_some(Symbol { addr: addr & !1, size, name })
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| symbol.addr);
+        Self { symbols }
+    }
+
+    /// Returns the function containing `pc` as `name+offset`.
+    pub fn lookup(&self, pc: u32) -> Option<String> {
+        let pc = u64::from(pc) & !1;
+        let index = self.symbols.partition_point(|symbol| symbol.addr <= pc).checked_sub(1)?;
+        let symbol = &self.symbols[index];
+        (pc < symbol.addr + symbol.size.max(1))
+            .then(|| format!("{}+0x{:x}", symbol.name, pc - symbol.addr))
+    }
+}
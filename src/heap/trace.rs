@@ -1,7 +1,13 @@
 //! Heap trace file.
+//!
+//! Extended sequences carry the block address after the sizes, and the caller
+//! PC after the address of an allocation. They start with a `0x?5` frame,
+//! continue with `0x?6` frames, and end with a `0x?7` frame, which is padded
+//! with leading zeros to a whole number of 32-bit words.
 
+use serde::Serialize;
 use std::{
-    fs::File,
+    convert::TryInto,
     io,
     io::{BufReader, Read},
     ops::{Generator, GeneratorState},
@@ -12,7 +18,14 @@ use thiserror::Error;
 /// The key used to shuffle packet bits.
 pub const KEY: u32 = 0xC5AC_CE55;
 
-const MAX_FRAME: usize = 8;
+const MAX_FRAME: usize = 12;
+
+/// Headers of the frames starting a sequence.
+const START_HEADERS: [u8; 8] = [0xA1, 0xD1, 0xB1, 0xC1, 0xA5, 0xD5, 0xB5, 0xC5];
+
+/// Number of bytes checked by a lenient parser to accept a resync point: the
+/// frame starting a sequence and the one following it.
+const RESYNC_WINDOW: usize = 8;
 
 /// Heap trace file parser error.
 #[derive(Error, Debug)]
@@ -30,20 +43,44 @@ pub enum Error {
 
 /// Heap trace file parser.
 pub struct Parser {
-    gen: Pin<Box<dyn Generator<Yield = Packet, Return = Result<(), Error>>>>,
+    gen: Pin<Box<dyn Generator<Yield = Token, Return = Result<(), Error>>>>,
+    corruptions: Vec<Corruption>,
+}
+
+/// Corrupted region of a trace, skipped by a lenient [`Parser`].
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Corruption {
+    /// Offset of the first skipped byte.
+    pub offset: u64,
+    /// Number of skipped bytes.
+    pub len: u64,
 }
 
 /// Heap trace file packet.
+///
+/// The block address and the caller PC are present only in the extended
+/// trace frames.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
 pub enum Packet {
     /// Allocate a block of memory.
     Alloc {
         /// Block size.
         size: u32,
+        /// Block address.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        addr: Option<u32>,
+        /// Caller PC.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pc: Option<u32>,
     },
     /// Deallocate a block of memory.
     Dealloc {
         /// Block size.
         size: u32,
+        /// Block address.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        addr: Option<u32>,
     },
     /// Extend a memory block.
     Grow {
@@ -51,6 +88,9 @@ pub enum Packet {
         old_size: u32,
         /// New block size.
         new_size: u32,
+        /// Block address.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        addr: Option<u32>,
     },
     /// Shrink a memory block.
     Shrink {
@@ -58,9 +98,26 @@ pub enum Packet {
         old_size: u32,
         /// New block size.
         new_size: u32,
+        /// Block address.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        addr: Option<u32>,
     },
 }
 
+enum Token {
+    Packet(Packet),
+    Corruption(Corruption),
+}
+
+#[derive(Default)]
+struct Sequences {
+    alloc: Vec<Frame>,
+    dealloc: Vec<Frame>,
+    grow_in_place: Vec<Frame>,
+    shrink_in_place: Vec<Frame>,
+    extended: [Vec<Frame>; 4],
+}
+
 #[derive(Default, Debug)]
 struct Frame {
     buf: [u8; MAX_FRAME],
@@ -69,11 +126,26 @@ struct Frame {
 }
 
 impl Parser {
-    /// Create a new [`Parser`] from file.
-    pub fn new(trace_file: File) -> Result<Self, Error> {
-        let reader = BufReader::new(trace_file);
-        let gen = Box::pin(parser(reader));
-        Ok(Self { gen })
+    /// Create a new [`Parser`] from a trace file or a live trace stream.
+    ///
+    /// A strict parser fails on the first invalid frame. A lenient parser
+    /// skips the corrupted bytes up to the next frame starting a sequence,
+    /// which is followed by a valid frame, and records the skipped region in
+    /// [`Parser::corruptions`].
+    pub fn new<R: Read + 'static>(trace: R, lenient: bool) -> Result<Self, Error> {
+        let reader = BufReader::new(trace);
+        let gen = Box::pin(parser(reader, lenient));
+        Ok(Self { gen, corruptions: Vec::new() })
+    }
+
+    /// Returns the corrupted regions skipped so far.
+    pub fn corruptions(&self) -> &[Corruption] {
+        &self.corruptions
+    }
+
+    /// Returns the number of bytes skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.corruptions.iter().map(|corruption| corruption.len).sum()
     }
 }
 
@@ -81,16 +153,168 @@ impl Iterator for Parser {
     type Item = Result<Packet, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.gen.as_mut().resume(()) {
-            GeneratorState::Yielded(packet) => Some(Ok(packet)),
-            GeneratorState::Complete(Ok(())) => None,
-            GeneratorState::Complete(Err(Error::Io(ref err)))
-                if err.kind() == io::ErrorKind::UnexpectedEof =>
-            {
-                None
+        loop {
+            break match self.gen.as_mut().resume(()) {
+                GeneratorState::Yielded(Token::Packet(packet)) => Some(Ok(packet)),
+                GeneratorState::Yielded(Token::Corruption(corruption)) => {
+                    self.corruptions.push(corruption);
+                    continue;
+                }
+                GeneratorState::Complete(Ok(())) => None,
+                GeneratorState::Complete(Err(Error::Io(ref err)))
+                    if err.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    None
+                }
+                GeneratorState::Complete(Err(err)) => Some(Err(err)),
+            };
+        }
+    }
+}
+
+impl Sequences {
+    #[allow(clippy::too_many_lines)]
+    fn push(&mut self, frame: [u8; 4]) -> Result<Option<Packet>, Error> {
+        let header = frame[0];
+        let payload = &frame[1..];
+        log::trace!(
+            "FRAME: 0x({:02X}){:02X}{:02X}{:02X}",
+            header,
+            payload[0],
+            payload[1],
+            payload[2]
+        );
+        match header {
+            0xA1 => {
+                let mut frame = Frame::default();
+                frame.push(payload)?;
+                self.alloc.push(frame);
+            }
+            0xD1 => {
+                let mut frame = Frame::default();
+                frame.push(payload)?;
+                self.dealloc.push(frame);
+            }
+            0xB1 => {
+                let mut frame = Frame::default();
+                frame.push(payload)?;
+                self.grow_in_place.push(frame);
+            }
+            0xC1 => {
+                let mut frame = Frame::default();
+                frame.push(payload)?;
+                self.shrink_in_place.push(frame);
+            }
+            0xB2 => {
+                self.grow_in_place.last_mut().ok_or(Error::InvalidSequence)?.push(payload)?;
             }
-            GeneratorState::Complete(Err(err)) => Some(Err(err)),
+            0xC2 => {
+                self.shrink_in_place.last_mut().ok_or(Error::InvalidSequence)?.push(payload)?;
+            }
+            0xA2 => {
+                let mut frame = self.alloc.pop().ok_or(Error::InvalidSequence)?;
+                if payload[0] != 0 || payload[1] != 0 {
+                    return Err(Error::InvalidFrame);
+                }
+                frame.push(&payload[2..])?;
+                let size = frame.pop_u32()?;
+                if !frame.is_empty() {
+                    return Err(Error::InvalidSequence);
+                }
+                log::debug!("Alloc: 0x{:08X}", size);
+                return Ok(Some(Packet::Alloc { size, addr: None, pc: None }));
+            }
+            0xD2 => {
+                let mut frame = self.dealloc.pop().ok_or(Error::InvalidSequence)?;
+                if payload[0] != 0 || payload[1] != 0 {
+                    return Err(Error::InvalidFrame);
+                }
+                frame.push(&payload[2..])?;
+                let size = frame.pop_u32()?;
+                if !frame.is_empty() {
+                    return Err(Error::InvalidSequence);
+                }
+                log::debug!("Dealloc: 0x{:08X}", size);
+                return Ok(Some(Packet::Dealloc { size, addr: None }));
+            }
+            0xB3 => {
+                let mut frame = self.grow_in_place.pop().ok_or(Error::InvalidSequence)?;
+                if payload[0] != 0 {
+                    return Err(Error::InvalidFrame);
+                }
+                frame.push(&payload[1..])?;
+                let old_size = frame.pop_u32()?;
+                let new_size = frame.pop_u32()?;
+                if !frame.is_empty() {
+                    return Err(Error::InvalidSequence);
+                }
+                log::debug!("Grow: 0x{:08X} -> 0x{:08X}", old_size, new_size);
+                return Ok(Some(Packet::Grow { old_size, new_size, addr: None }));
+            }
+            0xC3 => {
+                let mut frame = self.shrink_in_place.pop().ok_or(Error::InvalidSequence)?;
+                if payload[0] != 0 {
+                    return Err(Error::InvalidFrame);
+                }
+                frame.push(&payload[1..])?;
+                let old_size = frame.pop_u32()?;
+                let new_size = frame.pop_u32()?;
+                if !frame.is_empty() {
+                    return Err(Error::InvalidSequence);
+                }
+                log::debug!("Shrink: 0x{:08X} -> 0x{:08X}", old_size, new_size);
+                return Ok(Some(Packet::Shrink { old_size, new_size, addr: None }));
+            }
+            0xA5 | 0xD5 | 0xB5 | 0xC5 => {
+                let mut frame = Frame::default();
+                frame.push(payload)?;
+                self.extended(header).push(frame);
+            }
+            0xA6 | 0xD6 | 0xB6 | 0xC6 => {
+                self.extended(header).last_mut().ok_or(Error::InvalidSequence)?.push(payload)?;
+            }
+            0xA7 | 0xD7 | 0xB7 | 0xC7 => {
+                let mut frame = self.extended(header).pop().ok_or(Error::InvalidSequence)?;
+                let padding = (frame.tail + payload.len()) % 4;
+                if payload[..padding].iter().any(|&byte| byte != 0) {
+                    return Err(Error::InvalidFrame);
+                }
+                frame.push(&payload[padding..])?;
+                let mut values = Vec::with_capacity(MAX_FRAME / 4);
+                while !frame.is_empty() {
+                    values.push(frame.pop_u32()?);
+                }
+                let packet = match (header, values.as_slice()) {
+                    (0xA7, &[size, addr]) => Packet::Alloc { size, addr: Some(addr), pc: None },
+                    (0xA7, &[size, addr, pc]) => {
+                        Packet::Alloc { size, addr: Some(addr), pc: Some(pc) }
+                    }
+                    (0xD7, &[size, addr]) => Packet::Dealloc { size, addr: Some(addr) },
+                    (0xB7, &[old_size, new_size, addr]) => {
+                        Packet::Grow { old_size, new_size, addr: Some(addr) }
+                    }
+                    (0xC7, &[old_size, new_size, addr]) => {
+                        Packet::Shrink { old_size, new_size, addr: Some(addr) }
+                    }
+                    _ => return Err(Error::InvalidSequence),
+                };
+                log::debug!("Extended: {:?}", packet);
+                return Ok(Some(packet));
+            }
+            _ => return Err(Error::InvalidFrame),
         }
+        Ok(None)
+    }
+
+    /// Returns the pending extended sequences of the same kind as `header`.
+    fn extended(&mut self, header: u8) -> &mut Vec<Frame> {
+        let kind = match header >> 4 {
+            0xA => 0,
+            0xD => 1,
+            0xB => 2,
+            _ => 3,
+        };
+        &mut self.extended[kind]
     }
 }
 
@@ -124,111 +348,158 @@ impl Frame {
     }
 }
 
-#[allow(clippy::too_many_lines)]
 fn parser<R: Read>(
     mut reader: BufReader<R>,
-) -> impl Generator<Yield = Packet, Return = Result<(), Error>> {
-    let mut frame = [0; 4];
-    let mut alloc = Vec::new();
-    let mut dealloc = Vec::new();
-    let mut grow_in_place = Vec::new();
-    let mut shrink_in_place = Vec::new();
+    lenient: bool,
+) -> impl Generator<Yield = Token, Return = Result<(), Error>> {
+    let mut raw = [0; 4];
+    let mut offset = 0;
+    let mut sequences = Sequences::default();
     static move || {
         loop {
-            reader.read_exact(&mut frame)?;
-            frame = (u32::from_le_bytes(frame) ^ KEY).to_be_bytes();
-            let header = frame[0];
-            let payload = &frame[1..];
-            log::trace!(
-                "FRAME: 0x({:02X}){:02X}{:02X}{:02X}",
-                header,
-                payload[0],
-                payload[1],
-                payload[2]
-            );
-            match header {
-                0xA1 => {
-                    let mut frame = Frame::default();
-                    frame.push(payload)?;
-                    alloc.push(frame);
-                }
-                0xD1 => {
-                    let mut frame = Frame::default();
-                    frame.push(payload)?;
-                    dealloc.push(frame);
-                }
-                0xB1 => {
-                    let mut frame = Frame::default();
-                    frame.push(payload)?;
-                    grow_in_place.push(frame);
-                }
-                0xC1 => {
-                    let mut frame = Frame::default();
-                    frame.push(payload)?;
-                    shrink_in_place.push(frame);
-                }
-                0xB2 => {
-                    grow_in_place.last_mut().ok_or(Error::InvalidSequence)?.push(payload)?;
-                }
-                0xC2 => {
-                    shrink_in_place.last_mut().ok_or(Error::InvalidSequence)?.push(payload)?;
-                }
-                0xA2 => {
-                    let mut frame = alloc.pop().ok_or(Error::InvalidSequence)?;
-                    if payload[0] != 0 || payload[1] != 0 {
-                        break Err(Error::InvalidFrame);
-                    }
-                    frame.push(&payload[2..])?;
-                    let size = frame.pop_u32()?;
-                    if !frame.is_empty() {
-                        break Err(Error::InvalidSequence);
-                    }
-                    log::debug!("Alloc: 0x{:08X}", size);
-                    yield Packet::Alloc { size };
-                }
-                0xD2 => {
-                    let mut frame = dealloc.pop().ok_or(Error::InvalidSequence)?;
-                    if payload[0] != 0 || payload[1] != 0 {
-                        break Err(Error::InvalidFrame);
-                    }
-                    frame.push(&payload[2..])?;
-                    let size = frame.pop_u32()?;
-                    if !frame.is_empty() {
-                        break Err(Error::InvalidSequence);
-                    }
-                    log::debug!("Dealloc: 0x{:08X}", size);
-                    yield Packet::Dealloc { size };
-                }
-                0xB3 => {
-                    let mut frame = grow_in_place.pop().ok_or(Error::InvalidSequence)?;
-                    if payload[0] != 0 {
-                        break Err(Error::InvalidFrame);
+            reader.read_exact(&mut raw)?;
+            let start = offset;
+            offset += 4;
+            match sequences.push(decode(raw)) {
+                Ok(Some(packet)) => yield Token::Packet(packet),
+                Ok(None) => {}
+                Err(err) if !lenient => break Err(err),
+                Err(err) => {
+                    log::debug!("Corrupted frame at 0x{:X}: {}", start, err);
+                    let mut window = raw.to_vec();
+                    let mut position = start;
+                    loop {
+                        window.remove(0);
+                        position += 1;
+                        let missing = RESYNC_WINDOW - window.len();
+                        reader.by_ref().take(missing as u64).read_to_end(&mut window)?;
+                        if window.len() < 4 {
+                            yield Token::Corruption(Corruption {
+                                offset: start,
+                                len: position + window.len() as u64 - start,
+                            });
+                            return Ok(());
+                        }
+                        if is_resync_point(&window) {
+                            break;
+                        }
                     }
-                    frame.push(&payload[1..])?;
-                    let old_size = frame.pop_u32()?;
-                    let new_size = frame.pop_u32()?;
-                    if !frame.is_empty() {
-                        break Err(Error::InvalidSequence);
+                    yield Token::Corruption(Corruption { offset: start, len: position - start });
+                    offset = position + (window.len() - window.len() % 4) as u64;
+                    sequences = Sequences::default();
+                    for raw in window.chunks_exact(4) {
+                        if let Some(packet) = sequences.push(decode(raw.try_into().unwrap()))? {
+                            yield Token::Packet(packet);
+                        }
                     }
-                    log::debug!("Grow: 0x{:08X} -> 0x{:08X}", old_size, new_size);
-                    yield Packet::Grow { old_size, new_size };
                 }
-                0xC3 => {
-                    let mut frame = shrink_in_place.pop().ok_or(Error::InvalidSequence)?;
-                    if payload[0] != 0 {
-                        break Err(Error::InvalidFrame);
-                    }
-                    frame.push(&payload[1..])?;
-                    let old_size = frame.pop_u32()?;
-                    let new_size = frame.pop_u32()?;
-                    if !frame.is_empty() {
-                        break Err(Error::InvalidSequence);
-                    }
-                    log::debug!("Shrink: 0x{:08X} -> 0x{:08X}", old_size, new_size);
-                    yield Packet::Shrink { old_size, new_size };
-                }
-                _ => break Err(Error::InvalidFrame),
             }
         }
     }
 }
+
+/// Returns `true` if `window` starts with a frame starting a sequence, and the
+/// frame following it, unless the trace ends before, continues without errors.
+fn is_resync_point(window: &[u8]) -> bool {
+    let mut sequences = Sequences::default();
+    window.chunks_exact(4).enumerate().all(|(i, raw)| {
+        let frame = decode(raw.try_into().unwrap());
+        (i > 0 || START_HEADERS.contains(&frame[0])) && sequences.push(frame).is_ok()
+    })
+}
+
+fn decode(raw: [u8; 4]) -> [u8; 4] {
+    (u32::from_le_bytes(raw) ^ KEY).to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(frame: [u8; 4]) -> [u8; 4] {
+        (u32::from_be_bytes(frame) ^ KEY).to_le_bytes()
+    }
+
+    fn alloc(size: u8) -> Vec<u8> {
+        [encode([0xA1, 0, 0, 0]), encode([0xA2, 0, 0, size])].concat()
+    }
+
+    fn dealloc(size: u8) -> Vec<u8> {
+        [encode([0xD1, 0, 0, 0]), encode([0xD2, 0, 0, size])].concat()
+    }
+
+    fn parse(trace: Vec<u8>, lenient: bool) -> (Vec<Result<Packet, Error>>, Vec<Corruption>) {
+        let mut parser = Parser::new(io::Cursor::new(trace), lenient).unwrap();
+        let mut packets = Vec::new();
+        for packet in &mut parser {
+            let error = packet.is_err();
+            packets.push(packet);
+            if error {
+                break;
+            }
+        }
+        (packets, parser.corruptions().to_vec())
+    }
+
+    fn sizes(packets: &[Result<Packet, Error>]) -> Vec<u32> {
+        packets
+            .iter()
+            .map(|packet| match packet {
+                Ok(Packet::Alloc { size, .. } | Packet::Dealloc { size, .. }) => *size,
+                packet => panic!("unexpected {:?}", packet),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clean() {
+        for &lenient in &[false, true] {
+            let (packets, corruptions) = parse([alloc(16), dealloc(16)].concat(), lenient);
+            assert_eq!(sizes(&packets), [16, 16]);
+            assert!(matches!(packets[1], Ok(Packet::Dealloc { addr: None, .. })));
+            assert!(corruptions.is_empty());
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for &lenient in &[false, true] {
+            let mut trace = [alloc(16), alloc(32)].concat();
+            trace.truncate(trace.len() - 2);
+            let (packets, corruptions) = parse(trace, lenient);
+            assert_eq!(sizes(&packets), [16]);
+            assert!(corruptions.is_empty());
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let trace = [alloc(16), vec![0xFF; 3], alloc(32)].concat();
+        let (packets, _) = parse(trace.clone(), false);
+        assert_eq!(sizes(&packets[..1]), [16]);
+        assert!(matches!(packets[1], Err(Error::InvalidFrame)));
+        let (packets, corruptions) = parse(trace, true);
+        assert_eq!(sizes(&packets), [16, 32]);
+        assert_eq!(corruptions.len(), 1);
+        assert_eq!((corruptions[0].offset, corruptions[0].len), (8, 3));
+    }
+
+    #[test]
+    fn corrupted_at_end() {
+        let (packets, corruptions) = parse([alloc(16), vec![0xFF; 6]].concat(), true);
+        assert_eq!(sizes(&packets), [16]);
+        assert_eq!((corruptions[0].offset, corruptions[0].len), (8, 6));
+    }
+
+    #[test]
+    fn false_resync_point() {
+        // A frame starting a sequence, followed by a frame which can't
+        // continue it, is skipped as a part of the corrupted region.
+        let fake = [encode([0xA1, 0, 0, 0]), encode([0xA2, 1, 0, 0])].concat();
+        let trace = [alloc(16), vec![0xFF], fake, alloc(32), dealloc(32)].concat();
+        let (packets, corruptions) = parse(trace, true);
+        assert_eq!(sizes(&packets), [16, 32, 32]);
+        assert_eq!(corruptions.len(), 1);
+        assert_eq!((corruptions[0].offset, corruptions[0].len), (8, 9));
+    }
+}
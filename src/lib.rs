@@ -32,6 +32,7 @@
 #![feature(never_type)]
 // #![feature(type_alias_impl_trait)]
 #![feature(min_type_alias_impl_trait)]
+#![cfg_attr(test, feature(test))]
 // TODO document later #![warn(missing_docs, unsafe_op_in_unsafe_fn)]
 #![warn(clippy::pedantic)]
 #![allow(
@@ -54,6 +55,7 @@ pub mod cmd;
 pub mod color;
 pub mod crates;
 pub mod devices;
+pub mod edit;
 pub mod heap;
 pub mod log;
 pub mod probe;
@@ -68,7 +70,7 @@ use env_logger::Builder as LoggerBuilder;
 impl Cli {
     /// Runs the program.
     pub async fn run(self) -> Result<()> {
-        let Self { cmd, color, verbosity } = self;
+        let Self { cmd, color, verbosity, package } = self;
         let log_level = match verbosity {
             0 => Level::Error,
             1 => Level::Warn,
@@ -81,13 +83,15 @@ impl Cli {
             .filter(None, Level::Warn.to_level_filter())
             .try_init()?;
         match cmd {
-            Cmd::Flash(cmd) => cmd::flash(cmd).await,
-            Cmd::Gdb(cmd) => cmd::gdb(cmd).await,
-            Cmd::Heap(cmd) => cmd::heap(cmd, color),
-            Cmd::Log(cmd) => cmd::log(cmd, color).await,
+            Cmd::Config(cmd) => cmd::config(cmd, &package, color).await,
+            Cmd::Flash(cmd) => cmd::flash(cmd, &package).await,
+            Cmd::Gdb(cmd) => cmd::gdb(cmd, &package).await,
+            Cmd::Heap(cmd) => cmd::heap(cmd, &package, color).await,
+            Cmd::Log(cmd) => cmd::log(cmd, &package, color).await,
+            Cmd::Migrate(cmd) => cmd::migrate(cmd, &package, color).await,
             Cmd::New(cmd) => cmd::new(cmd, color).await,
-            Cmd::Reset(cmd) => cmd::reset(cmd).await,
-            Cmd::Print(cmd) => cmd::print(cmd, color).await,
+            Cmd::Reset(cmd) => cmd::reset(cmd, &package).await,
+            Cmd::Print(cmd) => cmd::print(cmd, &package, color).await,
         }
     }
 }
@@ -9,7 +9,7 @@ const KEY: u8 = 0b100_1011;
 /// Creates a new DSO parser.
 pub fn parser(
     mut outputs: OutputMap,
-) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>>>> {
+) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>> + Send>> {
     // let mut outputs = OutputMap::from(outputs);
     let mut payload = Vec::with_capacity(16);
     Box::pin(static move |mut byte| {
@@ -4,47 +4,194 @@ pub mod dso;
 pub mod swo;
 
 mod output;
+mod recording;
+mod serial;
+mod source;
 
-pub use self::output::{Output, OutputMap, OutputStream};
+pub use self::{
+    output::{Output, OutputMap, OutputStream},
+    recording::{Chunk, Recorder, Recording},
+    source::{Input, InputSpec, LogSource},
+};
 
-use anyhow::Result;
+use crate::{
+    color::Color,
+    probe::Log,
+    utils::{SignalStream, WithSignals},
+};
+use ansi_term::Color::{Green, Yellow};
+use anyhow::{anyhow, Result};
 use std::{
+    io::Read,
     ops::{Generator, GeneratorState},
     pin::Pin,
+    time::Duration,
 };
-use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
-use crate::color::Color;
-use std::io::Read;
+use tokio::{sync::oneshot, task::JoinHandle, time::sleep};
+
+/// Constructor of a log protocol parser.
+pub type ParserFn<'a> =
+    fn(OutputMap) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>> + 'a + Send>>;
+
+/// Size of the input read buffer.
+const BUFFER_SIZE: usize = 4096;
 
-type ParserFn<'a> = fn(OutputMap) -> Pin<Box<dyn Generator<u8, Yield = (), Return = Result<!>> + 'a + Send>>;
+/// Delay between attempts to reopen a disconnected input.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
 
-/// Runs log capture thread.
-pub fn capture<'a>(serial_endpoint: &str, baud_rate: u32,
-    // input: impl AsyncRead + Send + 'static,
+/// Running log capture.
+pub struct Capture {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl Capture {
+    /// Stops the capture between two reads, and waits for it to finish.
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        self.task.await?
+    }
+
+    /// Waits for the end of the input, or stops the capture on a signal.
+    pub async fn wait(self, signals: &mut SignalStream) -> Result<()> {
+        let Self { stop, mut task } = self;
+        match (&mut task).with_signals(signals, false).await {
+            Ok(result) => result,
+            Err(err) => {
+                let _ = stop.send(());
+                task.await??;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Returns the parser of the `log` protocol.
+pub fn parser(log: Log) -> ParserFn<'static> {
+    match log {
+        Log::SwoProbe | Log::SwoSerial => swo::parser,
+        Log::DsoSerial => dso::parser,
+    }
+}
+
+/// Runs log capture task.
+///
+/// Serial ports and TCP streams are reopened if the other side disappears,
+/// until the capture is stopped. The parser is recreated after reopening, as
+/// the input could be cut in the middle of a packet. Other sources finish the
+/// capture at the end of the input. The raw input is saved to `recorder` if
+/// given, along with the reconnection gaps.
+pub async fn capture(
+    source: LogSource,
     outputs: OutputMap,
     parser: ParserFn<'static>,
+    mut recorder: Option<Recorder>,
     color: Color,
-) {
+) -> Result<Capture> {
+    let mut input =
+        Some(source.open().await.map_err(|err| anyhow!("Couldn't open `{}`: {}", source, err))?);
+    let (stop, mut stopped) = oneshot::channel();
     begin_log_output(color);
-    let serial = mio_serial::new(serial_endpoint, baud_rate).open().unwrap();
-    // for byte in serial.bytes() {
-    //     sender.send(byte.unwrap()).await.unwrap();
-    // }
-    tokio::spawn(async move {
-        let parser = parser(outputs);
-        futures::pin_mut!(parser);
-        // let input = BufReader::new(input);
-        // futures::pin_mut!(input);
-        let mut bytes = serial.bytes();
+    let task = tokio::spawn(async move {
+        let new_parser = parser;
+        let mut parser = new_parser(outputs.try_clone()?);
+        let mut buf = vec![0; BUFFER_SIZE];
         loop {
-            let byte = tokio::task::block_in_place(|| bytes.next().unwrap().unwrap());
-            log::debug!("BYTE 0b{0:08b} 0x{0:02X} {1:?}", byte, char::from(byte));
+            let result = if let Some(opened) = &mut input {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    result = opened.read(&mut buf) => result,
+                }
+            } else {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    () = sleep(RECONNECT_DELAY) => {
+                        if let Ok(opened) = source.open().await {
+                            input = Some(opened);
+                            parser = new_parser(outputs.try_clone()?);
+                            if let Some(recorder) = &mut recorder {
+                                recorder.gap()?;
+                            }
+                            connection_marker(&source, "RECONNECTED", Green, color);
+                        }
+                    }
+                }
+                continue;
+            };
+            match result {
+                Ok(count) if count > 0 => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.write(&buf[..count])?;
+                    }
+                    for &byte in &buf[..count] {
+                        log::debug!("BYTE 0b{0:08b} 0x{0:02X} {1:?}", byte, char::from(byte));
+                        match parser.as_mut().resume(byte) {
+                            GeneratorState::Yielded(()) => (),
+                            GeneratorState::Complete(Err(err)) => return Err(err),
+                        }
+                    }
+                }
+                Ok(_) if !source.reconnects() => break,
+                Err(err) if !source.reconnects() => return Err(err.into()),
+                result => {
+                    if let Err(err) = result {
+                        log::debug!("`{}` read failure: {}", source, err);
+                    }
+                    input = None;
+                    connection_marker(&source, "DISCONNECTED", Yellow, color);
+                }
+            }
+        }
+        if let Some(recorder) = recorder {
+            recorder.finish()?;
+        }
+        Ok(())
+    });
+    Ok(Capture { stop, task })
+}
+
+/// Feeds a raw log `recording` through the log `parser`.
+pub fn replay(
+    recording: impl Read,
+    outputs: OutputMap,
+    parser: ParserFn<'_>,
+    color: Color,
+) -> Result<()> {
+    let recording = Recording::open(recording)?;
+    begin_log_output(color);
+    feed(recording, outputs, parser)
+}
+
+/// Feeds the chunks of `recording` through the log `parser`. The parser is
+/// recreated after each gap.
+pub fn feed<R: Read>(
+    mut recording: Recording<R>,
+    outputs: OutputMap,
+    parser: ParserFn<'_>,
+) -> Result<()> {
+    let new_parser = || outputs.try_clone().map(parser);
+    let mut parser = new_parser()?;
+    while let Some((_, chunk)) = recording.next_chunk()? {
+        let chunk = match chunk {
+            Chunk::Data(chunk) => chunk,
+            Chunk::Gap => {
+                parser = new_parser()?;
+                continue;
+            }
+        };
+        for byte in chunk {
             match parser.as_mut().resume(byte) {
                 GeneratorState::Yielded(()) => (),
-                GeneratorState::Complete(Err(err)) => panic!("log parser failure: {}", err),
+                GeneratorState::Complete(Err(err)) => return Err(err),
             }
         }
-    });
+    }
+    Ok(())
+}
+
+/// Displays a marker of the input connection change within the log output.
+fn connection_marker(source: &LogSource, event: &str, fg: ansi_term::Color, color: Color) {
+    eprintln!("{}", color.bold_fg(&format!("{:-^80}", format!(" {} {} ", source, event)), fg));
 }
 
 /// Displays a banner representing beginning of log output.
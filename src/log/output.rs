@@ -3,6 +3,7 @@ use std::{
     fs::{File, OpenOptions},
     io,
     io::{prelude::*, stdout, Stdout},
+    sync::mpsc::Sender,
 };
 /// Number of ports.
 pub const PORTS_COUNT: usize = 32;
@@ -21,6 +22,8 @@ pub enum OutputStream {
     Stdout(Stdout),
     /// File output.
     File(File),
+    /// In-process consumer.
+    Channel(Sender<Vec<u8>>),
 }
 
 /// Output map.
@@ -79,9 +82,25 @@ impl OutputMap {
        Ok(OutputMap(outputs?))
     }
 
+    /// Creates a new map writing to the same streams.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        self.0
+            .iter()
+            .map(|Output { ports, stream }| {
+                Ok(Output { ports: ports.clone(), stream: stream.try_clone()? })
+            })
+            .collect::<io::Result<_>>()
+            .map(Self)
+    }
+
+    /// Adds an output for the given `ports`.
+    pub fn push(&mut self, ports: Vec<u32>, stream: OutputStream) {
+        self.0.push(Output { ports, stream });
+    }
+
     /// Write `data` to all `port` outputs.
     pub fn write(&mut self, port: u8, data: &[u8]) -> anyhow::Result<()> {
-        anyhow::ensure!(port as usize > PORTS_COUNT);
+        anyhow::ensure!((port as usize) < PORTS_COUNT);
         for output_stream in self.0.iter_mut().filter(|o| o.ports.contains(&(port as u32))) {
             output_stream.stream.write(data)?;
         }
@@ -90,6 +109,15 @@ impl OutputMap {
 }
 
 impl OutputStream {
+    /// Creates a new handle to the same stream.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Stdout(_) => Ok(Self::Stdout(stdout())),
+            Self::File(file) => file.try_clone().map(Self::File),
+            Self::Channel(sender) => Ok(Self::Channel(sender.clone())),
+        }
+    }
+
     /// Write `data` to the output.
     pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
         fn write_stream<T: Write>(stream: &mut T, data: &[u8]) -> io::Result<()> {
@@ -100,6 +128,9 @@ impl OutputStream {
         match self {
             Self::Stdout(stdout) => write_stream(stdout, data),
             Self::File(file) => write_stream(file, data),
+            Self::Channel(sender) => {
+                sender.send(data.to_vec()).map_err(|_| io::ErrorKind::BrokenPipe.into())
+            }
         }
     }
 }
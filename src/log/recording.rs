@@ -0,0 +1,236 @@
+//! Raw log capture recording.
+//!
+//! A recording starts with the [`MAGIC`] signature, followed by the chunks of
+//! the raw input in the order they were read. Each chunk is a little-endian
+//! `u64` of microseconds since the start of the recording, a little-endian
+//! `u32` length, and the bytes themselves. A chunk with the length of
+//! [`GAP`] and no bytes marks a reconnection of the input.
+//!
+//! A [`Recording`] can also read a raw capture without the signature, as a
+//! single untimed stream.
+
+use super::BUFFER_SIZE;
+use anyhow::{anyhow, bail, Result};
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{self, prelude::*, BufReader, BufWriter},
+    mem,
+    path::Path,
+    time::Instant,
+};
+
+/// Signature of the recording file.
+pub const MAGIC: [u8; 8] = *b"DRONELG1";
+
+/// Chunk length which marks a gap in the input.
+pub const GAP: u32 = u32::MAX;
+
+/// Chunk of a raw log capture.
+#[derive(Debug, PartialEq)]
+pub enum Chunk {
+    /// Bytes of the input.
+    Data(Vec<u8>),
+    /// The input was reconnected, so the bytes around are not contiguous.
+    Gap,
+}
+
+/// Writer of a raw log capture.
+pub struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+/// Reader of a raw log capture.
+pub struct Recording<R: Read> {
+    reader: BufReader<R>,
+    offset: u64,
+    /// Leading bytes of a capture without the signature.
+    raw: Option<Vec<u8>>,
+}
+
+impl Recorder {
+    /// Creates a new recording at `path`.
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|err| anyhow!("Couldn't create `{}`: {}", path.display(), err))?;
+        file.write_all(&MAGIC)?;
+        Ok(Self { file, start: Instant::now() })
+    }
+
+    /// Appends a chunk of the raw input, timestamped with the current time.
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.write_header(chunk.len() as u32)?;
+        self.file.write_all(chunk)
+    }
+
+    /// Appends a gap marker, timestamped with the current time.
+    pub fn gap(&mut self) -> io::Result<()> {
+        self.write_header(GAP)
+    }
+
+    fn write_header(&mut self, len: u32) -> io::Result<()> {
+        let timestamp = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.file.write_all(&timestamp.to_le_bytes())?;
+        self.file.write_all(&len.to_le_bytes())
+    }
+
+    /// Flushes the recording.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl<R: Read> Recording<R> {
+    /// Opens a recording from `reader`, checking the signature.
+    pub fn open(reader: R) -> Result<Self> {
+        let recording = Self::detect(reader)?;
+        if recording.raw.is_some() {
+            bail!("Not a log recording");
+        }
+        Ok(recording)
+    }
+
+    /// Opens a recording from `reader` if it starts with the signature, or a
+    /// raw capture otherwise.
+    pub fn detect(reader: R) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = Vec::with_capacity(MAGIC.len());
+        reader.by_ref().take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+        let raw = (magic != MAGIC).then_some(magic);
+        Ok(Self { reader, offset: MAGIC.len() as u64, raw })
+    }
+
+    /// Reads the next chunk and its timestamp in microseconds. Returns `None`
+    /// at the end of the recording.
+    ///
+    /// Chunks of a raw capture are timestamped with zero.
+    pub fn next_chunk(&mut self) -> Result<Option<(u64, Chunk)>> {
+        if let Some(raw) = &mut self.raw {
+            let mut chunk = mem::take(raw);
+            let start = chunk.len();
+            chunk.resize(start + BUFFER_SIZE, 0);
+            let count = self.reader.read(&mut chunk[start..])?;
+            chunk.truncate(start + count);
+            return Ok((!chunk.is_empty()).then_some((0, Chunk::Data(chunk))));
+        }
+        let mut timestamp = [0; 8];
+        match self.reader.read(&mut timestamp[..1])? {
+            0 => return Ok(None),
+            _ => self.read_exact(&mut timestamp[1..])?,
+        }
+        let mut len = [0; 4];
+        self.read_exact(&mut len)?;
+        let (chunk, size) = match u32::from_le_bytes(len) {
+            GAP => (Chunk::Gap, 0),
+            size => {
+                let mut chunk = vec![0; size as usize];
+                self.read_exact(&mut chunk)?;
+                (Chunk::Data(chunk), size)
+            }
+        };
+        self.offset += (timestamp.len() + len.len() + size as usize) as u64;
+        Ok(Some((u64::from_le_bytes(timestamp), chunk)))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.reader.read_exact(buf) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                bail!("Truncated log recording chunk at offset {:#x}", self.offset)
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    fn record(chunks: &[&[u8]]) -> Vec<u8> {
+        let file = NamedTempFile::new().unwrap();
+        let mut recorder = Recorder::create(file.path()).unwrap();
+        for chunk in chunks {
+            if chunk.is_empty() { recorder.gap() } else { recorder.write(chunk) }.unwrap();
+        }
+        recorder.finish().unwrap();
+        fs::read(file.path()).unwrap()
+    }
+
+    fn chunks<R: Read>(mut recording: Recording<R>) -> Result<Vec<Vec<u8>>> {
+        let mut chunks = Vec::new();
+        while let Some((_, chunk)) = recording.next_chunk()? {
+            chunks.push(match chunk {
+                Chunk::Data(data) => data,
+                Chunk::Gap => Vec::new(),
+            });
+        }
+        Ok(chunks)
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = record(&[b"\x00\x80", b"", b"hello"]);
+        assert_eq!(&recording[..MAGIC.len()], &MAGIC);
+        let mut recording = Recording::open(&recording[..]).unwrap();
+        let mut timestamps = Vec::new();
+        let mut chunks = Vec::new();
+        while let Some((timestamp, chunk)) = recording.next_chunk().unwrap() {
+            timestamps.push(timestamp);
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks, [
+            Chunk::Data(b"\x00\x80".to_vec()),
+            Chunk::Gap,
+            Chunk::Data(b"hello".to_vec())
+        ]);
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn empty() {
+        assert!(chunks(Recording::open(&MAGIC[..]).unwrap()).unwrap().is_empty());
+        assert!(chunks(Recording::detect(&b""[..]).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn not_a_recording() {
+        for input in &[&b""[..], b"DRONE", b"DRONELG2\x00"] {
+            let err = Recording::open(*input).err().unwrap();
+            assert_eq!(err.to_string(), "Not a log recording");
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let recording = record(&[b"abc", b"", b"defg"]);
+        for len in MAGIC.len() + 1..recording.len() {
+            let result = chunks(Recording::open(&recording[..len]).unwrap());
+            match result {
+                Ok(chunks) if len == MAGIC.len() + 15 => assert_eq!(chunks, [&b"abc"[..]]),
+                Ok(chunks) => assert_eq!(chunks, [&b"abc"[..], b""], "{}", len),
+                Err(err) => {
+                    let offset = match len - MAGIC.len() {
+                        0..=14 => 0x8,
+                        15..=26 => 0x17,
+                        _ => 0x23,
+                    };
+                    let message = format!("Truncated log recording chunk at offset {:#x}", offset);
+                    assert_eq!(err.to_string(), message, "{}", len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raw_capture() {
+        for input in &[&b"\x00\x00\x00\x00\x00\x80\x03hi"[..], b"DRONE", b"DRONELG2"] {
+            assert_eq!(chunks(Recording::detect(*input).unwrap()).unwrap().concat(), *input);
+        }
+        let recording = record(&[b"abc"]);
+        assert_eq!(chunks(Recording::detect(&recording[..]).unwrap()).unwrap(), [b"abc"]);
+    }
+}
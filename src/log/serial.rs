@@ -0,0 +1,94 @@
+//! Asynchronous serial port input.
+
+use anyhow::Result;
+use std::io;
+
+/// Serial port opened for reading.
+#[cfg(unix)]
+pub struct Serial(tokio::io::unix::AsyncFd<mio_serial::SerialStream>);
+
+/// Serial port opened for reading.
+#[cfg(windows)]
+pub struct Serial {
+    port: Option<Box<dyn mio_serial::SerialPort>>,
+    closed: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(unix)]
+impl Serial {
+    /// Opens the serial port at `endpoint` in non-blocking mode.
+    pub fn open(endpoint: &str, baud_rate: u32) -> Result<Self> {
+        let stream = mio_serial::SerialStream::open(&mio_serial::new(endpoint, baud_rate))?;
+        Ok(Self(tokio::io::unix::AsyncFd::new(stream)?))
+    }
+
+    /// Reads the next chunk of bytes into `buf`. Returns `0` if the port is
+    /// gone.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::Read;
+        loop {
+            let mut guard = self.0.readable_mut().await?;
+            if let Ok(result) = guard.try_io(|stream| stream.get_mut().read(buf)) {
+                return result;
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Serial {
+    /// Polling interval of the blocking reads.
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+    /// Opens the serial port at `endpoint`.
+    pub fn open(endpoint: &str, baud_rate: u32) -> Result<Self> {
+        let port = mio_serial::new(endpoint, baud_rate).timeout(Self::TIMEOUT).open()?;
+        Ok(Self { port: Some(port), closed: Default::default() })
+    }
+
+    /// Reads the next chunk of bytes into `buf`. Returns `0` if the port is
+    /// gone.
+    ///
+    /// Windows serial ports can't be registered within the tokio reactor, so
+    /// the read is performed on the blocking thread pool. It is not
+    /// cancel-safe: if the returned future is dropped, the pending read keeps
+    /// the port open until its next polling timeout after the `Serial` is
+    /// dropped, and the bytes it reads are lost. Subsequent reads of the same
+    /// `Serial` return `0`, so the capture drops it and reopens the port
+    /// after the reconnect delay, which is longer than the timeout.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::{io::Read, sync::atomic::Ordering};
+        let mut port = match self.port.take() {
+            Some(port) => port,
+            None => return Ok(0),
+        };
+        let closed = std::sync::Arc::clone(&self.closed);
+        let mut chunk = vec![0; buf.len()];
+        let (port, result) = tokio::task::spawn_blocking(move || {
+            loop {
+                match port.read(&mut chunk) {
+                    Err(err)
+                        if err.kind() == io::ErrorKind::TimedOut
+                            && !closed.load(Ordering::Relaxed) =>
+                    {
+                        continue;
+                    }
+                    result => break (port, result.map(|count| chunk[..count].to_vec())),
+                }
+            }
+        })
+        .await?;
+        self.port = Some(port);
+        let chunk = result?;
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Serial {
+    fn drop(&mut self) {
+        // Stops the pending blocking read, which closes the port handle.
+        self.closed.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,208 @@
+//! Log input sources.
+
+use super::serial::Serial;
+use crate::probe::Log;
+use anyhow::{anyhow, bail, Error, Result};
+use std::{fmt, io, path::PathBuf, str::FromStr};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, Stdin},
+    net::TcpStream,
+};
+
+/// Log input source.
+#[derive(Clone, Debug)]
+pub enum LogSource {
+    /// Serial port (`serial:<path>[@<baud-rate>]`).
+    Serial {
+        /// Path to the serial port.
+        endpoint: String,
+        /// Baud rate, defaults to the one from Drone.toml.
+        baud_rate: Option<u32>,
+    },
+    /// TCP stream, such as OpenOCD's `tpiu config internal :<port>` output or
+    /// a remote serial server (`tcp:<host>:<port>`).
+    Tcp(String),
+    /// Regular file or named pipe (`file:<path>`, or just `<path>`).
+    File(PathBuf),
+    /// Standard input (`-`).
+    Stdin,
+}
+
+/// `drone log --input` argument.
+///
+/// The source can be prefixed with the stream protocol, `swo:` or `dso:`,
+/// which overrides the protocol of the configured debug logger.
+#[derive(Clone, Debug)]
+pub struct InputSpec {
+    /// Stream protocol.
+    pub log: Option<Log>,
+    /// Log source.
+    pub source: LogSource,
+}
+
+/// Opened log input.
+pub enum Input {
+    /// Serial port.
+    Serial(Serial),
+    /// TCP stream.
+    Tcp(TcpStream),
+    /// Regular file or named pipe.
+    File(File),
+    /// Standard input.
+    Stdin(Stdin),
+}
+
+impl LogSource {
+    /// Opens the source for reading.
+    pub async fn open(&self) -> Result<Input> {
+        match self {
+            Self::Serial { endpoint, baud_rate } => {
+                let baud_rate =
+                    baud_rate.ok_or_else(|| anyhow!("Unknown baud rate of `{}`", self))?;
+                Ok(Input::Serial(Serial::open(endpoint, baud_rate)?))
+            }
+            Self::Tcp(address) => Ok(Input::Tcp(TcpStream::connect(address).await?)),
+            Self::File(path) => Ok(Input::File(File::open(path).await?)),
+            Self::Stdin => Ok(Input::Stdin(tokio::io::stdin())),
+        }
+    }
+
+    /// Returns `true` if the source should be reopened after it is closed by
+    /// the other side.
+    pub fn reconnects(&self) -> bool {
+        match self {
+            Self::Serial { .. } | Self::Tcp(_) => true,
+            Self::File(_) | Self::Stdin => false,
+        }
+    }
+}
+
+impl Input {
+    /// Reads the next chunk of bytes into `buf`. Returns `0` at the end of the
+    /// input.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Serial(serial) => serial.read(buf).await,
+            Self::Tcp(stream) => stream.read(buf).await,
+            Self::File(file) => file.read(buf).await,
+            Self::Stdin(stdin) => stdin.read(buf).await,
+        }
+    }
+}
+
+impl FromStr for LogSource {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self> {
+        if src == "-" {
+            Ok(Self::Stdin)
+        } else if let Some(serial) = src.strip_prefix("serial:") {
+            let (endpoint, baud_rate) = match serial.rsplit_once('@') {
+                Some((endpoint, baud_rate)) => {
+                    let baud_rate = baud_rate
+                        .parse()
+                        .map_err(|err| anyhow!("Invalid baud rate in `{}`: {}", src, err))?;
+                    (endpoint, Some(baud_rate))
+                }
+                None => (serial, None),
+            };
+            if endpoint.is_empty() {
+                bail!("Missing serial port path in `{}`", src);
+            }
+            Ok(Self::Serial { endpoint: endpoint.to_owned(), baud_rate })
+        } else if let Some(address) = src.strip_prefix("tcp:") {
+            if !address.contains(':') {
+                bail!("Missing port in `{}`", src);
+            }
+            Ok(Self::Tcp(address.to_owned()))
+        } else {
+            Ok(Self::File(src.strip_prefix("file:").unwrap_or(src).into()))
+        }
+    }
+}
+
+impl FromStr for InputSpec {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self> {
+        let (log, source) = [("swo:", Log::SwoSerial), ("dso:", Log::DsoSerial)]
+            .iter()
+            .find_map(|&(prefix, log)| Some((Some(log), src.strip_prefix(prefix)?)))
+            .unwrap_or((None, src));
+        if source.is_empty() {
+            bail!("Missing log source in `{}`", src);
+        }
+        Ok(Self { log, source: source.parse()? })
+    }
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serial { endpoint, baud_rate: Some(baud_rate) } => {
+                write!(f, "serial:{}@{}", endpoint, baud_rate)
+            }
+            Self::Serial { endpoint, baud_rate: None } => write!(f, "serial:{}", endpoint),
+            Self::Tcp(address) => write!(f, "tcp:{}", address),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Stdin => write!(f, "-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ser_to_string;
+
+    fn error(src: &str) -> String {
+        src.parse::<InputSpec>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn serial() {
+        for (src, endpoint, baud_rate) in &[
+            ("serial:/dev/ttyACM0", "/dev/ttyACM0", None),
+            ("serial:/dev/ttyACM0@115200", "/dev/ttyACM0", Some(115_200)),
+            ("serial:COM3@9600", "COM3", Some(9600)),
+            ("serial:/dev/serial/by-id/usb@1@57600", "/dev/serial/by-id/usb@1", Some(57600)),
+        ] {
+            match src.parse::<LogSource>().unwrap() {
+                LogSource::Serial { endpoint: e, baud_rate: b } => {
+                    assert_eq!((e.as_str(), b), (*endpoint, *baud_rate), "{}", src);
+                }
+                source => panic!("{}: {:?}", src, source),
+            }
+        }
+    }
+
+    #[test]
+    fn protocols() {
+        for (src, log, source) in &[
+            ("swo:serial:/dev/ttyUSB0@2000000", Some("swoserial"), "serial:/dev/ttyUSB0@2000000"),
+            ("dso:tcp:localhost:4444", Some("dsoserial"), "tcp:localhost:4444"),
+            ("dso:-", Some("dsoserial"), "-"),
+            ("swo:capture.bin", Some("swoserial"), "file:capture.bin"),
+            ("tcp:127.0.0.1:3443", None, "tcp:127.0.0.1:3443"),
+            ("file:swo:capture.bin", None, "file:swo:capture.bin"),
+            ("-", None, "-"),
+        ] {
+            let spec = src.parse::<InputSpec>().unwrap();
+            assert_eq!(spec.log.map(ser_to_string).as_deref(), *log, "{}", src);
+            assert_eq!(spec.source.to_string(), *source, "{}", src);
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(error("serial:"), "Missing serial port path in `serial:`");
+        assert_eq!(error("serial:@115200"), "Missing serial port path in `serial:@115200`");
+        assert!(error("serial:/dev/ttyACM0@fast").starts_with("Invalid baud rate in `"));
+        assert!(error("serial:/dev/ttyACM0@").starts_with("Invalid baud rate in `"));
+        assert!(error("serial:/dev/ttyACM0@-1").starts_with("Invalid baud rate in `"));
+        assert_eq!(error("tcp:localhost"), "Missing port in `tcp:localhost`");
+        assert_eq!(error("swo:"), "Missing log source in `swo:`");
+        assert_eq!(error("dso:serial:"), "Missing serial port path in `serial:`");
+    }
+}
@@ -13,8 +13,9 @@ use tokio::process::Command;
 use drone_config as config;
 
 use crate::{
-    cli::{FlashCmd, GdbCmd, LogCmd, ResetCmd},
+    cli::{FlashCmd, GdbCmd, ResetCmd},
     color::Color,
+    log::{OutputMap, Recorder},
     templates::Registry,
     utils::{spawn_command},
 };
@@ -151,10 +152,13 @@ impl Probe {
 }
 
 /// Returns a function to serve `drone log` command.
+#[allow(clippy::too_many_arguments)]
 pub async fn log(
     probe: Probe,
     log: Log,
-    cmd: LogCmd,
+    reset: bool,
+    outputs: OutputMap,
+    recorder: Option<Recorder>,
     signals: SignalStream,
     registry: Registry<'_>,
     config: config::Config,
@@ -165,8 +169,9 @@ pub async fn log(
         //     Some(bmp::log_swo_serial(cmd, signals, registry, config, color).await),
         // (Probe::Jlink, Log::DsoSerial) =>
         //     Some(jlink::log_dso_serial(cmd, signals, registry, config, color).await),
-        (Probe::Openocd, Log::SwoProbe | Log::SwoSerial) =>
-            Some(openocd::log_swo(cmd, signals, registry, config, color).await),
+        (Probe::Openocd, Log::SwoProbe | Log::SwoSerial) => {
+            Some(openocd::log_swo(reset, outputs, recorder, signals, registry, config, color).await)
+        }
         _ => None,
     }
 }
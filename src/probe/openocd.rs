@@ -5,7 +5,7 @@ use super::{
     run_gdb_server, rustc_substitute_path,
 };
 use crate::{
-    cli::{FlashCmd, GdbCmd, LogCmd, ResetCmd},
+    cli::{FlashCmd, GdbCmd, ResetCmd},
     color::Color,
     log,
     templates::Registry,
@@ -16,15 +16,10 @@ use drone_config as config;
 use tokio::process::{Command, Child};
 use crate::utils::{SignalStream, WithSignals};
 use std::path::PathBuf;
-use std::io::{Read, stderr};
-use tokio::io::{AsyncRead, ReadBuf, Error, ErrorKind, AsyncWriteExt};
 use std::net::{Ipv4Addr, SocketAddr, IpAddr};
 use tokio::net::{TcpListener, TcpSocket};
-use tokio::sync::mpsc;
-use std::task::{Context, Poll};
-use std::pin::Pin;
 use crate::probe::run_gdb_client;
-use crate::log::OutputMap;
+use crate::log::{LogSource, OutputMap, Recorder};
 use drone_config::ProbeOpenocd;
 use std::str::FromStr;
 use std::collections::BTreeSet;
@@ -130,13 +125,14 @@ impl OpenocdCommand {
 
 /// Runs `drone log` command.
 pub async fn log_swo(
-    cmd: LogCmd,
+    reset: bool,
+    outputs: OutputMap,
+    recorder: Option<Recorder>,
     mut signals: SignalStream,
     _: Registry<'_>,
     config: config::Config,
     color: Color,
 ) -> Result<()> {
-    let LogCmd { reset, outputs } = cmd;
     let config_probe_openocd = config.probe.as_ref().unwrap().openocd.as_ref().unwrap();
     let config_log_swo = config.log.as_ref().unwrap().swo.as_ref().unwrap();
 
@@ -174,13 +170,18 @@ pub async fn log_swo(
             config_log_swo.baud_rate,
         ));
 
-        log::capture(serial_endpoint, config_log_swo.baud_rate,/*input, */OutputMap::new(&outputs)?, log::swo::parser, color);
+        let source = LogSource::Serial {
+            endpoint: serial_endpoint.clone(),
+            baud_rate: Some(config_log_swo.baud_rate),
+        };
+        let capture = log::capture(source, outputs, log::swo::parser, recorder, color).await?;
 
         let mut openocd = openocd.spawn()?;
 
-        let exit_code = openocd.wait().with_signals(&mut signals, true).await?;
+        let exit_code = openocd.wait().with_signals(&mut signals, true).await;
+        capture.stop().await?;
+        exit_code?;
 
-        // let input = serial_input(serial_endpoint, config_log_swo.baud_rate).await?;
         // stream.write(format!(
         //     "tpiu config external uart off {} {}",
         //     config_log_swo.reset_freq,
@@ -205,28 +206,3 @@ pub async fn log_swo(
 
     Ok(())
 }
-
-async fn serial_input(serial_endpoint: &str, baud_rate: u32) -> Result<impl AsyncRead + Send>{
-    let (sender, receiver) = mpsc::channel(512);
-    let serial = mio_serial::new(serial_endpoint, baud_rate).open()?;
-    tokio::spawn(async move {
-        for byte in serial.bytes() {
-            sender.send(byte.unwrap()).await.unwrap();
-        }
-    });
-    Ok(AsyncReadReceiver(receiver))
-}
-
-struct AsyncReadReceiver(mpsc::Receiver<u8>);
-
-impl AsyncRead for AsyncReadReceiver{
-    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
-        self.get_mut().0.poll_recv(cx).map(|x| match x {
-            None => Err(Error::from(ErrorKind::Other)),
-            Some(byte) => {
-                buf.put_slice(&[byte]);
-                Ok(())
-            }
-        })
-    }
-}
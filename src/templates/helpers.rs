@@ -17,7 +17,14 @@ thread_local! {
 
 handlebars_helper!(addr: |num: u64| format!("0x{:08x}", num));
 
-handlebars_helper!(size: |num: u64| format_size(num as u32));
+handlebars_helper!(size: |num: u64| {
+    // Linker scripts don't support `G` suffix.
+    if num > 0 && num % (1024 * 1024 * 1024) == 0 {
+        format!("{}M", num / (1024 * 1024))
+    } else {
+        format_size(num)
+    }
+});
 
 handlebars_helper!(upcase: |value: str| value.to_screaming_snake_case());
 
@@ -3,6 +3,7 @@
 pub mod helpers;
 
 use crate::{
+    crates,
     devices::Device,
     probe::{Log, Probe},
     utils::{ser_to_string},
@@ -35,6 +36,7 @@ impl Registry<'_> {
         template!("new/src/tasks/root.rs")?;
         template!("new/Cargo.toml")?;
         template!("new/Drone.toml")?;
+        template!("new/build.rs")?;
         template!("new/Justfile")?;
         template!("new/rust-toolchain")?;
         template!("new/_cargo/config")?;
@@ -147,6 +149,7 @@ impl Registry<'_> {
         let data = json!({
             "contents": contents,
             "crate_name": crate_name,
+            "drone_version": crates::VERSION,
             "platform_name": device.platform_crate.krate.name(),
             "bindings_name": device.bindings_crate.krate.name(),
             "platform_features": device.platform_crate.features,
@@ -162,8 +165,8 @@ impl Registry<'_> {
     pub fn new_drone_toml(
         &self,
         device: &Device,
-        flash_size: u32,
-        ram_size: u32,
+        flash_size: u64,
+        ram_size: u64,
         heap: &str,
         probe: Probe,
         log: Log,
@@ -187,6 +190,12 @@ impl Registry<'_> {
         Ok(self.0.render("new/Drone.toml", &data)?)
     }
 
+    /// Renders `build.rs`.
+    pub fn new_build_rs(&self) -> Result<String> {
+        helpers::clear_vars();
+        Ok(self.0.render("new/build.rs", &())?)
+    }
+
     /// Renders `Justfile`.
     pub fn new_justfile(&self) -> Result<String> {
         helpers::clear_vars();
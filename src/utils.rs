@@ -1,7 +1,7 @@
 //! Utility functions.
 use std::{
     env,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
 };
 use std::future::Future;
@@ -10,6 +10,7 @@ use std::task::{Context, Poll};
 
 use ansi_term::Color::Red;
 use anyhow::{bail, Result, anyhow};
+use drone_config::{Config, CONFIG_NAME};
 use futures::Stream;
 use futures::prelude::*;
 use futures::StreamExt;
@@ -19,9 +20,10 @@ use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use tokio::process::{Child, Command};
+use toml::Value;
 use walkdir::WalkDir;
 
-use crate::color::Color;
+use crate::{cli::PackageOpts, color::Color};
 
 /// Runs the application code inside closure `f`, prints an error using `color`
 /// preference if there is any, and sets the exit code.
@@ -43,13 +45,102 @@ pub async fn run_wrapper(
     }
 }
 
-/// Returns the current crate root.
-pub async fn crate_root() -> Result<PathBuf> {
+/// Returns the root directory of the selected crate.
+///
+/// If `--package` or `--manifest-path` is given, the crate is resolved through
+/// `cargo metadata`. Otherwise the closest directory containing `Drone.toml` is
+/// searched upwards from the current directory.
+pub async fn crate_root(opts: &PackageOpts) -> Result<PathBuf> {
+    let PackageOpts { package, manifest_path, .. } = opts;
+    if package.is_none() && manifest_path.is_none() {
+        let current_dir = env::current_dir()?;
+        return current_dir
+            .ancestors()
+            .find(|dir| dir.join(CONFIG_NAME).exists())
+            .map(Path::to_path_buf)
+            .ok_or_else(|| {
+                anyhow!(
+                    "`{}` not found in `{}` or any parent directory",
+                    CONFIG_NAME,
+                    current_dir.display()
+                )
+            });
+    }
+    let metadata = cargo_metadata(manifest_path.as_deref()).await?;
+    let manifest = if let Some(package) = package {
+        metadata
+            .packages
+            .into_iter()
+            .find(|candidate| candidate.name == *package)
+            .ok_or_else(|| anyhow!("Package `{}` not found in the workspace", package))?
+            .manifest_path
+    } else {
+        let manifest_path = manifest_path.as_ref().unwrap().canonicalize()?;
+        metadata
+            .packages
+            .into_iter()
+            .map(|candidate| candidate.manifest_path)
+            .find(|candidate| *candidate == manifest_path)
+            .ok_or_else(|| {
+                anyhow!(
+                    "`{}` is a virtual manifest, select a package with `--package`",
+                    manifest_path.display()
+                )
+            })?
+    };
+    Ok(manifest.parent().unwrap().to_path_buf())
+}
+
+/// Reads the configuration of the selected crate with the selected board
+/// section.
+pub async fn read_config(opts: &PackageOpts) -> Result<Config> {
+    Config::read_for_board(&crate_root(opts).await?, opts.board.as_deref())
+}
+
+/// Returns the Cargo target directory for the crate at `crate_root`.
+pub async fn target_dir(crate_root: &Path) -> Result<PathBuf> {
+    Ok(cargo_metadata(Some(&crate_root.join("Cargo.toml"))).await?.target_directory)
+}
+
+/// Returns the `.cargo/config` file applying to the crate at `crate_root`.
+///
+/// Like Cargo, the closest `.cargo/config` or `.cargo/config.toml` is
+/// searched upwards from `crate_root`, but not above the workspace root. If
+/// there is none, the path inside `crate_root` is returned.
+pub async fn cargo_config_path(crate_root: &Path) -> Result<PathBuf> {
+    let workspace_root = cargo_metadata(Some(&crate_root.join("Cargo.toml"))).await?.workspace_root;
+    let crate_root = crate_root.canonicalize()?;
+    for dir in crate_root.ancestors() {
+        if let Some(path) = cargo_config_in(dir) {
+            return Ok(path);
+        }
+        if dir == workspace_root {
+            break;
+        }
+    }
+    Ok(crate_root.join(".cargo").join("config"))
+}
+
+/// Returns the `.cargo/config` or `.cargo/config.toml` file in `dir`, the
+/// former taking precedence as in Cargo.
+fn cargo_config_in(dir: &Path) -> Option<PathBuf> {
+    ["config", "config.toml"]
+        .iter()
+        .map(|name| dir.join(".cargo").join(name))
+        .find(|path| path.exists())
+}
+
+async fn cargo_metadata(manifest_path: Option<&Path>) -> Result<CargoMetadata> {
     let mut cargo = Command::new("cargo");
-    cargo.arg("locate-project").arg("--message-format=plain");
-    let mut root = PathBuf::from(String::from_utf8(cargo.output().await?.stdout)?);
-    root.pop();
-    Ok(root)
+    cargo.arg("metadata").arg("--format-version=1").arg("--no-deps");
+    if let Some(manifest_path) = manifest_path {
+        cargo.arg("--manifest-path").arg(manifest_path);
+    }
+    let output = cargo.output().await?;
+    if !output.status.success() {
+        bail!("`cargo metadata` failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
 }
 
 /// Searches for the Rust tool `tool` in the sysroot.
@@ -326,6 +417,21 @@ pub fn temp_dir() -> PathBuf {
 //     }
 // }
 
+#[non_exhaustive]
+#[derive(Clone, Debug, serde::Deserialize)]
+struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+    target_directory: PathBuf,
+    workspace_root: PathBuf,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Debug, serde::Deserialize)]
+struct CargoPackage {
+    name: String,
+    manifest_path: PathBuf,
+}
+
 #[non_exhaustive]
 #[derive(Clone, Debug, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -340,22 +446,23 @@ struct CargoConfigBuild {
     target: Option<String>,
 }
 
-/// Resolve target for current crate
-pub async fn resolve_target() -> Result<String>{
-    let crate_root = crate_root().await?.canonicalize()?;
-    let path = crate_root.join(".cargo").join("config");
-    if !path.exists() {
-        bail!("`{}` does not exist in `{}", path.display(), crate_root.display());
+/// Resolves the target triple for the crate at `crate_root` from the closest
+/// `.cargo/config` or `.cargo/config.toml` which sets it.
+pub async fn resolve_target(crate_root: &Path) -> Result<String> {
+    let crate_root = crate_root.canonicalize()?;
+    for path in crate_root.ancestors().filter_map(cargo_config_in) {
+        let mut buffer = String::new();
+        let mut file = File::open(&path).await?;
+        file.read_to_string(&mut buffer).await?;
+        let config = toml::from_str::<CargoConfig>(&buffer)?;
+        if let Some(target) = config.build.and_then(|build| build.target) {
+            return Ok(target);
+        }
     }
-    let mut buffer = String::new();
-    let mut file = File::open(&path).await?;
-    file.read_to_string(&mut buffer).await?;
-    let config = toml::from_str::<CargoConfig>(&buffer)?;
-    let target = config
-        .build
-        .and_then(|build| build.target)
-        .ok_or_else(|| anyhow!("No [build.target] configuration in {:?}", path))?;
-    Ok(target)
+    bail!(
+        "No [build.target] configuration in `.cargo/config` or `.cargo/config.toml` for `{}`",
+        crate_root.display()
+    )
 }
 
 /// Serialize the value to a string.
@@ -368,6 +475,55 @@ pub fn de_from_str<T: de::DeserializeOwned>(s: &str) -> Result<T> {
     serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(Into::into)
 }
 
+/// Renders a TOML `value` on a single line.
+pub fn inline_value(value: &Value) -> String {
+    match value {
+        Value::Array(array) => {
+            format!("[{}]", array.iter().map(inline_value).collect::<Vec<_>>().join(", "))
+        }
+        Value::Table(table) => {
+            let pairs = table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, inline_value(value)))
+                .collect::<Vec<_>>();
+            format!("{{ {} }}", pairs.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("signal")]
 struct SignalError;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn resolve_target_from_config_toml() {
+        let dir = TempDir::new().unwrap();
+        let crate_root = dir.path().join("app");
+        fs::create_dir_all(dir.path().join(".cargo")).unwrap();
+        fs::create_dir_all(crate_root.join(".cargo")).unwrap();
+        fs::write(
+            dir.path().join(".cargo/config.toml"),
+            "[build]\ntarget = 'thumbv7m-none-eabi'\n",
+        )
+        .unwrap();
+        fs::write(crate_root.join(".cargo/config.toml"), "[alias]\nb = 'build'\n").unwrap();
+        assert_eq!(resolve_target(&crate_root).await.unwrap(), "thumbv7m-none-eabi");
+        fs::write(
+            crate_root.join(".cargo/config"),
+            "[build]\ntarget = 'riscv32imac-unknown-none-elf'\n",
+        )
+        .unwrap();
+        assert_eq!(resolve_target(&crate_root).await.unwrap(), "riscv32imac-unknown-none-elf");
+        fs::remove_file(dir.path().join(".cargo/config.toml")).unwrap();
+        fs::remove_file(crate_root.join(".cargo/config")).unwrap();
+        let err = resolve_target(&crate_root).await.unwrap_err();
+        assert!(err.to_string().contains("`.cargo/config` or `.cargo/config.toml`"), "{}", err);
+    }
+}